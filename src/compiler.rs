@@ -0,0 +1,430 @@
+// Compiles parsed procedure bodies and the lists which get run into instructions for the stack
+// machine in the evaluator (Evaluator::run_code).  Evaluator::eval walks the parsed code directly
+// and stays around as the reference for what the instructions have to do.
+
+use std::rc::Rc;
+use parser::{AST, ListType, Operator, Parser, WordType};
+use value::{List, Value};
+
+// What a call calls, looked up when the code gets compiled: the index of a builtin, or the slot of
+// a user defined procedure, which stays with its name when it gets redefined or erased.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Callee {
+  Builtin(usize),
+  User(usize),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Instruction {
+  // Pushes a number, word or list.
+  Push(Value),
+  // Pushes the value of the variable.
+  Var(WordType),
+  // Operators pop their operands and push the result.
+  Negate,
+  Binary(Operator),
+  Comparison(Operator),
+  Nary(Operator, usize),
+  // Calls the procedure (named for the errors) with that many inputs from the top of the stack.
+  Call(WordType, Callee, usize, Use),
+  // The value on top of the stack isn't used by anything, which is an error.
+  Unused,
+  // Start of a line of the procedure, for the error messages.
+  Line(usize),
+  // Anything without an instruction of its own (TO) goes to Evaluator::eval.
+  Eval(AST, Use),
+  // IF, IFELSE, REPEAT, WHILE and UNTIL get their lists compiled in place.  Running a list is a
+  // level deeper, its instructions are between Enter and Leave.
+  Enter,
+  Leave,
+  Jump(usize),
+  // Pops the condition and jumps when it isn't the given one.  Named for the errors.
+  JumpUnless(bool, usize, WordType),
+  // Pops the number of passes of REPEAT and starts counting them for REPCOUNT.
+  Repeat,
+  // Starts the next pass, or jumps when they're done.
+  NextPass(usize),
+}
+
+// What happens with the output of a call.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Use {
+  // It's an input to the procedure or operator, so it has to output something.
+  Input(WordType),
+  // It's an instruction, outputting something is an error.
+  Instruction,
+  // It's the last expression of a list, its output (if any) is the output of the list.
+  Result,
+  // It's the last expression of the condition of WHILE or UNTIL (the list), it has to output.
+  Condition(List),
+  // It's the last instruction of a procedure, or the input to OUTPUT (named here) on its own.  A
+  // call to a user defined procedure then replaces the running one instead of nesting in it.
+  Tail,
+  TailOutput(WordType),
+}
+
+pub struct Code {
+  pub instructions: Vec<Instruction>,
+  // Where each line starts, GO continues from the line with the LABEL.
+  starts: Vec<usize>,
+  // Name of the procedure, None for a list.
+  pub procedure: Option<WordType>,
+}
+
+impl Code {
+  pub fn line_start(&self, line: usize) -> usize {
+    self.starts.get(line).cloned().unwrap_or(self.instructions.len())
+  }
+}
+
+// The lists of the control structures are parsed with the parser, the callees looked up with
+// callee (None when there's no such procedure).
+struct Compiler<'a> {
+  instructions: Vec<Instruction>,
+  parser: &'a Parser,
+  callee: &'a dyn Fn(&str) -> Option<Callee>,
+}
+
+impl<'a> Compiler<'a> {
+  fn new(parser: &'a Parser, callee: &'a dyn Fn(&str) -> Option<Callee>) -> Self {
+    Compiler { instructions: Vec::new(), parser, callee }
+  }
+
+  fn push(&mut self, instruction: Instruction) {
+    self.instructions.push(instruction);
+  }
+
+  // Points the jump at index to the next instruction.
+  fn patch(&mut self, index: usize) {
+    let next = self.instructions.len();
+    match self.instructions[index] {
+      Instruction::Jump(ref mut target) | Instruction::JumpUnless(_, ref mut target, _) |
+      Instruction::NextPass(ref mut target) => {
+        *target = next;
+      },
+      ref other => {
+        panic!("Not a jump {:?}", other);
+      },
+    }
+  }
+
+  fn expr(&mut self, ast_node: &AST, use_: Use) {
+    match ast_node {
+      AST::Call(name, args) if name != "TO" => {
+        self.call(name, args, use_);
+        return;
+      },
+      AST::Parens(expr_list) => {
+        // Like in eval, only the first expression counts, empty parens are the empty list.
+        match expr_list.front() {
+          Some(expr) => { self.expr(expr, use_); },
+          None => {
+            self.push(Instruction::Push(Value::List(List::new())));
+            self.unused(use_);
+          },
+        }
+        return;
+      },
+      AST::Num(num) => { self.push(Instruction::Push(Value::Num(*num))); },
+      AST::Word(word) => { self.push(Instruction::Push(Value::Word(word.clone()))); },
+      AST::List(list) => { self.push(Instruction::Push(Value::List(list.clone()))); },
+      AST::Var(var) => { self.push(Instruction::Var(var.clone())); },
+      AST::Negation(operand) => {
+        self.expr(operand, Use::Input("-".to_string()));
+        self.push(Instruction::Negate);
+      },
+      AST::Binary(operator, left, right) => {
+        self.expr(left, Use::Input(operator.to_string()));
+        self.expr(right, Use::Input(operator.to_string()));
+        self.push(Instruction::Binary(*operator));
+      },
+      AST::Comparison(operator, left, right) => {
+        self.expr(left, Use::Input(operator.to_string()));
+        self.expr(right, Use::Input(operator.to_string()));
+        self.push(Instruction::Comparison(*operator));
+      },
+      AST::Nary(operator, expr_list) => {
+        for expr in expr_list {
+          self.expr(expr, Use::Input(operator.to_string()));
+        }
+        self.push(Instruction::Nary(*operator, expr_list.len()));
+      },
+      other => {
+        self.push(Instruction::Eval(other.clone(), use_));
+        return;
+      },
+    }
+    self.unused(use_);
+  }
+
+  fn call(&mut self, name: &WordType, args: &ListType, use_: Use) {
+    if self.control(name, args, &use_) {
+      return;
+    }
+    let callee = match (self.callee)(name) {
+      Some(callee) => { callee },
+      None => {
+        // Eval tells it doesn't know how.
+        self.push(Instruction::Eval(AST::Call(name.clone(), args.clone()), use_));
+        return;
+      },
+    };
+    for arg in args {
+      self.expr(arg, Use::Input(name.clone()));
+    }
+    self.push(Instruction::Call(name.clone(), callee, args.len(), use_));
+  }
+
+  // Compiles IF, IFELSE, REPEAT, WHILE and UNTIL in place when their lists are written out and
+  // parse, and nothing needs an output from them.  Gives false for the calls it leaves alone.
+  fn control(&mut self, name: &str, args: &ListType, use_: &Use) -> bool {
+    if !["IF", "IFELSE", "REPEAT", "WHILE", "UNTIL"].contains(&name) {
+      return false;
+    }
+    // IF and IFELSE output what the list does, which goes where theirs would.  Not as a tail call,
+    // running the list is a level deeper.
+    let last_use = match use_ {
+      Use::Instruction | Use::Tail => { Use::Instruction },
+      Use::Result => { Use::Result },
+      _ => { return false; },
+    };
+    let lists: Option<Vec<Rc<ListType>>> = args.iter().skip(1).map(|arg| {
+      match arg {
+        AST::List(list) => { self.parser.parse_list(list).ok() },
+        _ => { None },
+      }
+    }).collect();
+    let lists = match lists {
+      Some(lists) => { lists },
+      None => { return false; },
+    };
+    match (name, args.len()) {
+      ("IF", 2) | ("IF", 3) | ("IFELSE", 3) => {
+        self.expr(&args[0], Use::Input(name.to_string()));
+        let jump = self.instructions.len();
+        self.push(Instruction::JumpUnless(true, 0, name.to_string()));
+        self.list(&lists[0], last_use.clone());
+        if let Some(else_list) = lists.get(1) {
+          let end = self.instructions.len();
+          self.push(Instruction::Jump(0));
+          self.patch(jump);
+          self.list(else_list, last_use);
+          self.patch(end);
+        } else {
+          self.patch(jump);
+        }
+      },
+      ("REPEAT", 2) => {
+        self.expr(&args[0], Use::Input(name.to_string()));
+        self.push(Instruction::Repeat);
+        let next = self.instructions.len();
+        self.push(Instruction::NextPass(0));
+        self.list(&lists[0], Use::Instruction);
+        self.push(Instruction::Jump(next));
+        self.patch(next);
+      },
+      ("WHILE", 2) | ("UNTIL", 2) => {
+        // The condition is the first input, it's a list too.
+        let condition = match args.front() {
+          Some(AST::List(condition)) => { condition },
+          _ => { return false; },
+        };
+        let exprs = match self.parser.parse_list(condition) {
+          Ok(ref exprs) if !exprs.is_empty() => { exprs.clone() },
+          _ => { return false; },
+        };
+        let start = self.instructions.len();
+        self.list(&exprs, Use::Condition(condition.clone()));
+        let jump = self.instructions.len();
+        self.push(Instruction::JumpUnless(name == "WHILE", 0, "the condition".to_string()));
+        self.list(&lists[0], Use::Instruction);
+        self.push(Instruction::Jump(start));
+        self.patch(jump);
+      },
+      _ => { return false; },
+    }
+    true
+  }
+
+  // A list compiled in place, the last expression does last_use with its output.
+  fn list(&mut self, exprs: &ListType, last_use: Use) {
+    self.push(Instruction::Enter);
+    for (i, expr) in exprs.iter().enumerate() {
+      self.expr(expr, if i + 1 < exprs.len() { Use::Instruction } else { last_use.clone() });
+    }
+    self.push(Instruction::Leave);
+  }
+
+  // Everything except calls always outputs, which is only fine when something uses the output.
+  fn unused(&mut self, use_: Use) {
+    if use_ == Use::Instruction {
+      self.push(Instruction::Unused);
+    }
+  }
+}
+
+// A call in tail position: the last instruction of a procedure (last tells if the expression is
+// that), or the input to OUTPUT when that's an instruction of the procedure.  Gives the called
+// procedure, its inputs and the name OUTPUT was called by.
+pub fn tail_call(expr: &AST, last: bool) -> Option<(&WordType, &ListType, Option<&WordType>)> {
+  match expr {
+    AST::Call(name, args) if name == "OP" || name == "OUTPUT" => {
+      match args.front() {
+        Some(AST::Call(callee, callee_args)) if args.len() == 1 && callee != "TO" => {
+          Some((callee, callee_args, Some(name)))
+        },
+        _ => { None },
+      }
+    },
+    AST::Call(name, args) if last && name != "TO" => { Some((name, args, None)) },
+    _ => { None },
+  }
+}
+
+// The expressions of a line of a procedure body.
+pub fn line_exprs(line: &AST) -> Vec<&AST> {
+  match line {
+    AST::ExprLine(expr_list) => { expr_list.iter().collect() },
+    other => { vec![other] },
+  }
+}
+
+// The lines of a procedure body, as they come from Parser::parse_lines.
+pub fn compile_procedure(name: &str, lines: &Rc<ListType>, parser: &Parser,
+                         callee: &dyn Fn(&str) -> Option<Callee>) -> Code {
+  let mut compiler = Compiler::new(parser, callee);
+  let mut starts = Vec::new();
+  for (i, line) in lines.iter().enumerate() {
+    starts.push(compiler.instructions.len());
+    compiler.push(Instruction::Line(i));
+    let exprs = line_exprs(line);
+    for (j, expr) in exprs.iter().enumerate() {
+      let last = i + 1 == lines.len() && j + 1 == exprs.len();
+      match tail_call(expr, last) {
+        Some((callee, args, output)) => {
+          let use_ = output.map_or(Use::Tail, |output| Use::TailOutput(output.clone()));
+          compiler.call(callee, args, use_);
+        },
+        None => { compiler.expr(expr, Use::Instruction); },
+      }
+    }
+  }
+  Code {
+    instructions: compiler.instructions,
+    starts,
+    procedure: Some(name.to_string()),
+  }
+}
+
+// A list that gets run (REPEAT 4 [FD 10 RT 90]), as it comes from Parser::parse_list.
+pub fn compile_list(exprs: &Rc<ListType>, parser: &Parser, callee: &dyn Fn(&str) -> Option<Callee>)
+    -> Code {
+  let mut compiler = Compiler::new(parser, callee);
+  for (i, expr) in exprs.iter().enumerate() {
+    compiler.expr(expr, if i + 1 < exprs.len() { Use::Instruction } else { Use::Result });
+  }
+  Code {
+    instructions: compiler.instructions,
+    starts: Vec::new(),
+    procedure: None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use parser::Arity;
+
+  #[test]
+  fn compile() {
+    let mut parser = Parser::new();
+    parser.set_arity("FD", Arity::fixed(1));
+    parser.set_arity("XCOR", Arity::fixed(0));
+    parser.set_arity("F", Arity::fixed(0));
+    let callee = |name: &str| {
+      match name {
+        "FD" => { Some(Callee::Builtin(0)) },
+        "XCOR" => { Some(Callee::Builtin(1)) },
+        "F" => { Some(Callee::User(0)) },
+        _ => { None },
+      }
+    };
+    let lines = Rc::new(parser.parse_lines(&["FD -(XCOR * 2) 3".to_string()]).unwrap());
+    assert_eq!(vec![
+      Instruction::Line(0),
+      Instruction::Call("XCOR".to_string(), Callee::Builtin(1), 0, Use::Input("*".to_string())),
+      Instruction::Push(Value::Num(2.0)),
+      Instruction::Binary(Operator::Multiply),
+      Instruction::Negate,
+      Instruction::Call("FD".to_string(), Callee::Builtin(0), 1, Use::Instruction),
+      Instruction::Push(Value::Num(3.0)),
+      Instruction::Unused,
+    ], compile_procedure("F", &lines, &parser, &callee).instructions);
+    let list = List::from(vec![Value::Word("FD".to_string()), Value::Num(1.0), Value::Num(2.0)]);
+    assert_eq!(vec![
+      Instruction::Push(Value::Num(1.0)),
+      Instruction::Call("FD".to_string(), Callee::Builtin(0), 1, Use::Instruction),
+      Instruction::Push(Value::Num(2.0)),
+    ], compile_list(&parser.parse_list(&list).unwrap(), &parser, &callee).instructions);
+  }
+
+  #[test]
+  fn compile_in_place() {
+    let mut parser = Parser::new();
+    parser.set_arity("FD", Arity::fixed(1));
+    parser.set_arity("IF", Arity::new(2, 2, Some(3)));
+    parser.set_arity("REPEAT", Arity::fixed(2));
+    parser.set_arity("WHILE", Arity::fixed(2));
+    parser.set_arity("F", Arity::fixed(0));
+    let callee = |name: &str| {
+      match name {
+        "FD" => { Some(Callee::Builtin(0)) },
+        "F" => { Some(Callee::User(0)) },
+        _ => { None },
+      }
+    };
+    let source = ["IF 1 [FD 1] [F]".to_string(), "REPEAT 2 [FD 2]".to_string(),
+                  "WHILE [0] [FD 3]".to_string(), "IF 1 [F]".to_string()];
+    let lines = Rc::new(parser.parse_lines(&source).unwrap());
+    let fd = Instruction::Call("FD".to_string(), Callee::Builtin(0), 1, Use::Instruction);
+    assert_eq!(vec![
+      Instruction::Line(0),
+      Instruction::Push(Value::Num(1.0)),
+      Instruction::JumpUnless(true, 8, "IF".to_string()),
+      Instruction::Enter,
+      Instruction::Push(Value::Num(1.0)),
+      fd.clone(),
+      Instruction::Leave,
+      Instruction::Jump(11),
+      Instruction::Enter,
+      Instruction::Call("F".to_string(), Callee::User(0), 0, Use::Instruction),
+      Instruction::Leave,
+      Instruction::Line(1),
+      Instruction::Push(Value::Num(2.0)),
+      Instruction::Repeat,
+      Instruction::NextPass(20),
+      Instruction::Enter,
+      Instruction::Push(Value::Num(2.0)),
+      fd.clone(),
+      Instruction::Leave,
+      Instruction::Jump(14),
+      Instruction::Line(2),
+      Instruction::Enter,
+      Instruction::Push(Value::Num(0.0)),
+      Instruction::Leave,
+      Instruction::JumpUnless(true, 30, "the condition".to_string()),
+      Instruction::Enter,
+      Instruction::Push(Value::Num(3.0)),
+      fd.clone(),
+      Instruction::Leave,
+      Instruction::Jump(21),
+      // The last instruction isn't a tail call when it's in a list.
+      Instruction::Line(3),
+      Instruction::Push(Value::Num(1.0)),
+      Instruction::JumpUnless(true, 36, "IF".to_string()),
+      Instruction::Enter,
+      Instruction::Call("F".to_string(), Callee::User(0), 0, Use::Instruction),
+      Instruction::Leave,
+    ], compile_procedure("F", &lines, &parser, &callee).instructions);
+  }
+}
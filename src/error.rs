@@ -0,0 +1,206 @@
+// Errors of the lexer, the parser and the evaluator: what went wrong (with what), and where.
+// They print like in PC Logo, "FD doesn't like [1 2] as input in DIJ: FD :A".
+
+use std::fmt;
+use parser::WordType;
+use value::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ErrorKind {
+  StackOverflow,
+  // The procedure and the input it didn't like.
+  DoesntLike(WordType, Value),
+  // What didn't output, and the procedure (or operator) that needed its output.
+  DidntOutput(WordType, WordType),
+  NotEnoughInputs(WordType),
+  TooManyInputs(WordType),
+  TooMuchInsideParens,
+  // A value which isn't used by anything.
+  UnusedOutput(Value),
+  // Variables created by LOCAL but never set, and ones which don't exist at all.
+  NoValue(WordType),
+  NotAName(WordType),
+  DontKnowHow(WordType),
+  // The list for WHILE, UNTIL, ... which doesn't output TRUE or FALSE.
+  NotCondition(Value),
+  CantFindLabel(WordType),
+  // THROW with a tag no CATCH is waiting for.
+  NoCatch(WordType),
+  // THROW "ERROR, with the message if there is one.
+  Thrown(Option<Value>),
+  DivideByZero,
+  NumberTooBig,
+  // Defining a procedure with the name of a builtin.
+  IsPrimitive(WordType),
+  // TO in the lines of the named procedure.
+  ToInDefinition(WordType),
+  // STOP, OUTPUT, GO or LOCAL at the top level.
+  OnlyInProcedure(WordType),
+  // A closing paren, bracket or brace without the opening one, and the other way around.
+  Unexpected(char),
+  NotFound(char),
+  // The file, and what the system said about it.
+  CantOpen(String, String),
+  CantRead(String, String),
+  CantWrite(String, String),
+}
+
+impl ErrorKind {
+  // The numbers follow the ones UCBLogo's ERROR outputs.
+  pub fn code(&self) -> i32 {
+    match self {
+      ErrorKind::StackOverflow => { 2 },
+      ErrorKind::DidntOutput(..) => { 5 },
+      ErrorKind::NotEnoughInputs(_) => { 6 },
+      ErrorKind::DoesntLike(..) | ErrorKind::NotCondition(_) |
+      ErrorKind::DivideByZero | ErrorKind::NumberTooBig => { 7 },
+      ErrorKind::TooManyInputs(_) | ErrorKind::TooMuchInsideParens => { 8 },
+      ErrorKind::UnusedOutput(_) => { 9 },
+      ErrorKind::NotFound(_) => { 10 },
+      ErrorKind::NoValue(_) | ErrorKind::NotAName(_) => { 11 },
+      ErrorKind::DontKnowHow(_) => { 13 },
+      ErrorKind::CantFindLabel(_) | ErrorKind::NoCatch(_) => { 14 },
+      ErrorKind::Thrown(None) => { 21 },
+      ErrorKind::Thrown(Some(_)) => { 35 },
+      ErrorKind::Unexpected(')') => { 12 },
+      ErrorKind::Unexpected('}') => { 27 },
+      ErrorKind::Unexpected(_) => { 26 },
+      ErrorKind::CantOpen(..) | ErrorKind::CantRead(..) | ErrorKind::CantWrite(..) => { 18 },
+      ErrorKind::IsPrimitive(_) => { 22 },
+      ErrorKind::ToInDefinition(_) => { 23 },
+      ErrorKind::OnlyInProcedure(_) => { 31 },
+    }
+  }
+}
+
+impl fmt::Display for ErrorKind {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      ErrorKind::StackOverflow => { write!(f, "Stack overflow") },
+      ErrorKind::DoesntLike(name, value) => { write!(f, "{} doesn't like {} as input", name, value) },
+      ErrorKind::DidntOutput(name, to) => { write!(f, "{} didn't output to {}", name, to) },
+      ErrorKind::NotEnoughInputs(name) => { write!(f, "Not enough inputs to {}", name) },
+      ErrorKind::TooManyInputs(name) => { write!(f, "Too many inputs to {}", name) },
+      ErrorKind::TooMuchInsideParens => { write!(f, "Too much inside ()") },
+      ErrorKind::UnusedOutput(value) => { write!(f, "You don't say what to do with {}", value) },
+      ErrorKind::NoValue(var) => { write!(f, "{} has no value", var) },
+      ErrorKind::NotAName(var) => { write!(f, ":{} is not a Logo name.", var) },
+      ErrorKind::DontKnowHow(name) => { write!(f, "I don't know how to {}", name) },
+      ErrorKind::NotCondition(list) => { write!(f, "Condition {} doesn't output TRUE or FALSE", list) },
+      ErrorKind::CantFindLabel(label) => { write!(f, "Can't find label {}", label) },
+      ErrorKind::NoCatch(tag) => { write!(f, "Can't find catch tag for {}", tag) },
+      ErrorKind::Thrown(None) => { write!(f, "Throw \"Error") },
+      // The message is printed like PR would.
+      ErrorKind::Thrown(Some(Value::List(list))) => {
+        write!(f, "{}", list.iter().map(|item| item.to_string()).collect::<Vec<String>>().join(" "))
+      },
+      ErrorKind::Thrown(Some(message)) => { write!(f, "{}", message) },
+      ErrorKind::DivideByZero => { write!(f, "Can't divide by zero") },
+      ErrorKind::NumberTooBig => { write!(f, "Number too big") },
+      ErrorKind::IsPrimitive(name) => { write!(f, "{} is already in use. Try a different name.", name) },
+      ErrorKind::ToInDefinition(name) => { write!(f, "TO inside of function definition {}", name) },
+      ErrorKind::OnlyInProcedure(name) => { write!(f, "Can only use {} inside a procedure", name) },
+      ErrorKind::Unexpected(c) => { write!(f, "Unexpected '{}'", c) },
+      ErrorKind::NotFound(c) => { write!(f, "'{}' not found", c) },
+      ErrorKind::CantOpen(file, err) => { write!(f, "Can't open file {}: {}", file, err) },
+      ErrorKind::CantRead(file, err) => { write!(f, "Can't read file {}: {}", file, err) },
+      ErrorKind::CantWrite(file, err) => { write!(f, "Can't write file {}: {}", file, err) },
+    }
+  }
+}
+
+// A line of a procedure.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Place {
+  pub procedure: WordType,
+  // As it was typed in, None when the error is about the whole procedure (a missing LABEL).
+  pub line: Option<String>,
+}
+
+impl fmt::Display for Place {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self.line {
+      Some(ref line) => { write!(f, "{}: {}", self.procedure, line) },
+      None => { write!(f, "{}", self.procedure) },
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogoError {
+  pub kind: ErrorKind,
+  // Where it happened, None at the top level.
+  pub place: Option<Place>,
+  // The calls of the procedures it happened in, the innermost first.  A tail call replaces the
+  // procedure making it, so that one isn't in here.
+  pub callers: Vec<Place>,
+}
+
+impl LogoError {
+  pub fn code(&self) -> i32 {
+    self.kind.code()
+  }
+
+  // The input that caused it, or the value nothing used.
+  #[allow(dead_code)]
+  pub fn value(&self) -> Option<&Value> {
+    match self.kind {
+      ErrorKind::DoesntLike(_, ref value) | ErrorKind::UnusedOutput(ref value) |
+      ErrorKind::NotCondition(ref value) => { Some(value) },
+      _ => { None },
+    }
+  }
+}
+
+impl From<ErrorKind> for LogoError {
+  fn from(kind: ErrorKind) -> Self {
+    LogoError { kind, place: None, callers: Vec::new() }
+  }
+}
+
+// The lexer and the parser give them boxed, they're big next to what they output.
+impl From<ErrorKind> for Box<LogoError> {
+  fn from(kind: ErrorKind) -> Self {
+    Box::new(LogoError::from(kind))
+  }
+}
+
+impl fmt::Display for LogoError {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{}", self.kind)?;
+    if let Some(ref place) = self.place {
+      write!(f, " in {}", place)?;
+    }
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use value::List;
+
+  #[test]
+  fn display() {
+    let list = Value::List(List::from(vec![Value::Num(1.0), Value::Num(2.0)]));
+    let mut err = LogoError::from(ErrorKind::DoesntLike("FD".to_string(), list.clone()));
+    assert_eq!("FD doesn't like [1 2] as input", err.to_string());
+    err.place = Some(Place { procedure: "DIJ".to_string(), line: Some("FD :A".to_string()) });
+    assert_eq!("FD doesn't like [1 2] as input in DIJ: FD :A", err.to_string());
+    assert_eq!((7, Some(&list)), (err.code(), err.value()));
+    let err = LogoError {
+      kind: ErrorKind::CantFindLabel("LOOP".to_string()),
+      place: Some(Place { procedure: "F".to_string(), line: None }),
+      callers: Vec::new(),
+    };
+    assert_eq!("Can't find label LOOP in F", err.to_string());
+    assert_eq!(14, err.code());
+    let message = Value::List(List::from(vec![Value::Word("BAD".to_string()), list]));
+    assert_eq!("BAD [1 2]", ErrorKind::Thrown(Some(message)).to_string());
+    assert_eq!(21, ErrorKind::Thrown(None).code());
+    let err = ErrorKind::Unexpected(']');
+    assert_eq!(("Unexpected ']'".to_string(), 26), (err.to_string(), err.code()));
+    let err = ErrorKind::NotFound(')');
+    assert_eq!(("')' not found".to_string(), 10), (err.to_string(), err.code()));
+  }
+}
@@ -1,36 +1,702 @@
+use compiler;
 use parser;
 use turtle;
 
-use std::cell::RefCell;
 use std::collections::HashMap; // TODO: Remove import, create types for the maps used.
-use std::collections::VecDeque; // TODO: Remove import, use proper named type.
 use std::io::BufRead;
-use parser::{AST, ListType, WordType, NumType};
-use lexer::Token;
+use std::rc::Rc;
+use compiler::{Callee, Code, Instruction, Use};
+use error::{ErrorKind, LogoError, Place};
+use parser::{AST, Arity, ListType, Operator, WordType, NumType};
+use value::{Array, List, Value};
 use scopeguard::guard;
 
 type ArgsType = Vec<String>;
-type BuiltinFunctionType = dyn Fn(&mut Evaluator) -> Result<AST, String>;
+// Variables created by LOCAL have no value until they're set.
+type VarsType = HashMap<String, Option<Value>>;
+// Properties of a property list, in the order they were first put in.
+type PropsType = Vec<(WordType, Value)>;
+
+// The args and the parsed lines are shared with the running calls, calling doesn't copy them and
+// redefining the procedure while it runs doesn't pull them out from under it.
+struct UserFunction {
+  args: Rc<ArgsType>,
+  // TO NAME :A :B, as PO prints it.
+  title: String,
+  // Lines as they were typed in, they get parsed on the first call because they can call
+  // procedures which are defined later.
+  source: Vec<String>,
+  lines: Option<Rc<ListType>>,
+  // Compiled from the lines, when running compiled code.
+  code: Option<Rc<Code>>,
+}
+
+// What PO and ERASE work on: the procedure names from a word or a list, or the names from a
+// contents list [[procedures] [variables] [property lists]] like the one NAMES outputs.
+struct Contents {
+  procedures: Vec<WordType>,
+  vars: Vec<WordType>,
+  plists: Vec<WordType>,
+}
+
+fn contents(name: &str, thing: &Value) -> Result<Contents, Unwind> {
+  match thing {
+    Value::List(list) if matches!(list.first(), Some(Value::List(_))) => {
+      if list.len() > 3 {
+        return Err(doesnt_like(name, thing));
+      }
+      let mut parts = Vec::new();
+      for part in list.iter() {
+        parts.push(words(name, part)?);
+      }
+      parts.resize(3, Vec::new());
+      let plists = parts.pop().unwrap();
+      let vars = parts.pop().unwrap();
+      Ok(Contents { procedures: parts.pop().unwrap(), vars, plists })
+    },
+    other => {
+      Ok(Contents { procedures: words(name, other)?, vars: Vec::new(), plists: Vec::new() })
+    },
+  }
+}
+
+// A procedure being defined, TO FOO :A starts it and feed collects the lines until END.
+struct Definition {
+  name: WordType,
+  args: ArgsType,
+  title: String,
+  lines: Vec<String>,
+}
+
+// How deep the stacks of the evaluator are.  What a call or a line of input pushes on them gets
+// dropped when it's done, the same when it ends with an error (Evaluator::with_stacks).
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Stacks {
+  vars: usize,
+  repcount: usize,
+  catch_tags: usize,
+  templates: usize,
+  depth: usize,
+}
+
+// Procedures and the lists which get run can be compiled and run on a stack machine, or run by
+// walking the parsed code, which is slower but simpler and kept as the reference.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+  Compile,
+  Interpret,
+}
+
+// Non-local exits which unwind the evaluation through lists, loops and parens.  STOP, OUTPUT and
+// GO are caught at the procedure boundary, THROW by the CATCH with its tag, and errors go all the
+// way up to the top level unless CATCH "ERROR is waiting for them.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Unwind {
+  // Boxed like the tail calls below.
+  Error(Box<LogoError>),
+  Stop,
+  Output(Value),
+  Go(WordType),
+  // The tag and the value (THROW "TAG 5), boxed like the tail calls.
+  Throw(Box<(WordType, Option<Value>)>),
+  // A call in tail position, the procedure making it is done and the called one (the name and the
+  // slot) runs in its place.  Boxed, every Result in the evaluator carries an Unwind and deep
+  // recursion adds them all up.
+  TailCall(Box<(WordType, usize, Vec<Value>, Tail)>),
+}
+
+// What the procedure making a tail call does with the output.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Tail {
+  // The call is the last instruction, there mustn't be any.  The caller and the line of the call.
+  Instruction(WordType, usize),
+  // The call is the input to OUTPUT (or OP).  The caller, the line, the called procedure and OP.
+  Output(WordType, usize, WordType, WordType),
+}
+
+impl From<LogoError> for Unwind {
+  fn from(err: LogoError) -> Self {
+    Unwind::Error(Box::new(err))
+  }
+}
+
+impl From<Box<LogoError>> for Unwind {
+  fn from(err: Box<LogoError>) -> Self {
+    Unwind::Error(err)
+  }
+}
+
+impl From<ErrorKind> for Unwind {
+  fn from(kind: ErrorKind) -> Self {
+    LogoError::from(kind).into()
+  }
+}
+
+fn doesnt_like(name: &str, thing: &Value) -> Unwind {
+  ErrorKind::DoesntLike(name.to_string(), thing.clone()).into()
+}
+
+// Math errors come back as Logo errors instead of NaN or inf.
+fn checked_num(name: &str, input: NumType, result: NumType) -> Result<Option<Value>, Unwind> {
+  if result.is_finite() {
+    Ok(Some(Value::Num(result)))
+  } else {
+    Err(doesnt_like(name, &Value::Num(input)))
+  }
+}
+
+// Logo has no separate boolean type, comparisons output the words "TRUE and "FALSE.
+fn bool_word(value: bool) -> Value {
+  Value::Word((if value { "TRUE" } else { "FALSE" }).to_string())
+}
+
+fn as_bool(thing: &Value) -> Option<bool> {
+  match thing {
+    Value::Word(word) if word == "TRUE" => { Some(true) },
+    Value::Word(word) if word == "FALSE" => { Some(false) },
+    _ => { None },
+  }
+}
+
+// Numbers are words too, FIRST 123 outputs 1 and COUNT 12345 outputs 5.
+fn to_word(thing: &Value) -> Option<WordType> {
+  match thing {
+    Value::Word(word) => { Some(word.clone()) },
+    Value::Num(num) => { Some(format!("{}", num)) },
+    _ => { None },
+  }
+}
+
+// The number a word looks like, BUTFIRST 100 outputs the word 00 which is 0 where a number is
+// needed.
+fn word_number(word: &str) -> Option<NumType> {
+  let numeric = word.chars().all(|c| c.is_ascii_digit() || c == '.' || c == '-');
+  if numeric { word.parse::<NumType>().ok() } else { None }
+}
+
+fn to_number(thing: &Value) -> Option<NumType> {
+  match thing {
+    Value::Num(num) => { Some(*num) },
+    // Words that look like numbers are numbers (FD "50).
+    Value::Word(word) => { word_number(word) },
+    _ => { None },
+  }
+}
+
+// Text of a word or list as PRINT shows it, the outermost brackets are left out.
+fn to_text(thing: &Value, brackets: bool) -> String {
+  match thing {
+    Value::List(list) if !brackets => {
+      list.iter().map(|item| item.to_string()).collect::<Vec<String>>().join(" ")
+    },
+    other => { other.to_string() },
+  }
+}
+
+// LOAD "FILE and SAVE "FILE work on file.lgo.
+fn file_name(word: &str) -> String {
+  let mut file_name = word.to_lowercase();
+  if !file_name.ends_with(".lgo") {
+    file_name += ".lgo";
+  }
+  file_name
+}
+
+// TO and END are recognized by the first word of the line.
+fn first_word(line: &str) -> Option<String> {
+  line.split_whitespace().next().map(|word| word.to_uppercase())
+}
+
+// A word, or the words in a list, as the names for ERN, ERASE, ...
+fn words(name: &str, thing: &Value) -> Result<Vec<WordType>, Unwind> {
+  match thing {
+    Value::Word(word) => { Ok(vec![word.clone()]) },
+    Value::List(list) => {
+      list.iter().map(|item| {
+        match item {
+          Value::Word(word) => { Ok(word.clone()) },
+          other => { Err(doesnt_like(name, other)) },
+        }
+      }).collect()
+    },
+    other => { Err(doesnt_like(name, other)) },
+  }
+}
+
+// A value as code that outputs it, "WORD, 5 or [1 2].
+fn quoted(thing: &Value) -> String {
+  match thing {
+    Value::Word(word) => { format!("\"{}", word) },
+    other => { other.to_string() },
+  }
+}
+
+fn values_equal(left: &Value, right: &Value) -> bool {
+  match (left, right) {
+    (Value::List(left), Value::List(right)) => {
+      left.len() == right.len() && left.iter().zip(right.iter()).all(|(l, r)| values_equal(l, r))
+    },
+    (Value::List(_), _) | (_, Value::List(_)) => { false },
+    (left, right) => {
+      // 5 and "5.0 are equal, "A and "A too.
+      if let (Some(left), Some(right)) = (to_number(left), to_number(right)) {
+        return left == right;
+      }
+      match (to_word(left), to_word(right)) {
+        (Some(left), Some(right)) => { left == right },
+        _ => { left == right },
+      }
+    },
+  }
+}
+
+// Splits a non-empty word or list into its first item and the rest (FIRST and BUTFIRST).
+fn split_first(thing: &Value) -> Option<(Value, Value)> {
+  if let Value::List(list) = thing {
+    return Some((list.first()?.clone(), Value::List(list.butfirst()?)));
+  }
+  let word = to_word(thing)?;
+  let first = word.chars().next()?;
+  Some((Value::Word(first.to_string()), Value::Word(word[first.len_utf8()..].to_string())))
+}
+
+// Splits a non-empty word or list into its last item and the rest (LAST and BUTLAST).
+fn split_last(thing: &Value) -> Option<(Value, Value)> {
+  if let Value::List(list) = thing {
+    return Some((list.last()?.clone(), Value::List(list.butlast()?)));
+  }
+  let word = to_word(thing)?;
+  let last = word.chars().last()?;
+  let rest = word[..word.len() - last.len_utf8()].to_string();
+  Some((Value::Word(last.to_string()), Value::Word(rest)))
+}
+
+// The items of a list, or the characters of a word, for MAP, FILTER, ...
+fn items(name: &str, thing: &Value) -> Result<Vec<Value>, Unwind> {
+  match thing {
+    Value::List(list) => { Ok(list.iter().cloned().collect()) },
+    other => {
+      let word = to_word(other).ok_or_else(|| doesnt_like(name, other))?;
+      Ok(word.chars().map(|c| Value::Word(c.to_string())).collect())
+    },
+  }
+}
+
+// Puts the items back together into the same kind of thing as like: a list, or a word of words.
+fn from_items(name: &str, like: &Value, items: Vec<Value>) -> Result<Value, Unwind> {
+  if let Value::List(_) = like {
+    return Ok(Value::List(List::from(items)));
+  }
+  let mut word = WordType::new();
+  for item in items {
+    word += &to_word(&item).ok_or_else(|| doesnt_like(name, &item))?;
+  }
+  Ok(Value::Word(word))
+}
+
+// The inputs for each pass of (MAP template data1 data2), the items of the data side by side.
+fn zip_items(name: &str, data: &[Value]) -> Result<Vec<Vec<Value>>, Unwind> {
+  let mut rows: Vec<Vec<Value>> = Vec::new();
+  for (i, thing) in data.iter().enumerate() {
+    let items = items(name, thing)?;
+    if i == 0 {
+      rows = items.into_iter().map(|item| vec![item]).collect();
+    } else if items.len() != rows.len() {
+      return Err(doesnt_like(name, thing));
+    } else {
+      for (row, item) in rows.iter_mut().zip(items) {
+        row.push(item);
+      }
+    }
+  }
+  Ok(rows)
+}
+
+// All the combinations of the items, for CROSSMAP, the first data changes the slowest.
+fn cross_items(name: &str, data: &[Value]) -> Result<Vec<Vec<Value>>, Unwind> {
+  let mut rows = vec![Vec::new()];
+  for thing in data {
+    let items = items(name, thing)?;
+    rows = rows.iter().flat_map(|row| items.iter().map(move |item| {
+      let mut row = row.clone();
+      row.push(item.clone());
+      row
+    })).collect();
+  }
+  Ok(rows)
+}
+
+// Indexes and sizes of arrays, 2.5 isn't one.
+fn index(name: &str, thing: &Value) -> Result<i32, Unwind> {
+  match to_number(thing) {
+    Some(num) if num.fract() == 0.0 => { Ok(num as i32) },
+    _ => { Err(doesnt_like(name, thing)) },
+  }
+}
+
+// An array of arrays for each of the sizes but the last, MDARRAY.
+fn md_array(sizes: &[usize], origin: i32) -> Value {
+  let items = match sizes.split_first() {
+    Some((&size, [])) => { vec![Value::List(List::new()); size] },
+    Some((&size, rest)) => { (0 .. size).map(|_| md_array(rest, origin)).collect() },
+    None => { Vec::new() },
+  };
+  Value::Array(Array::new(items, origin))
+}
+
+// Goes through the arrays inside arrays with the indexes.
+fn md_item(name: &str, indexes: &List, mut thing: Value) -> Result<Value, Unwind> {
+  for index_ in indexes.iter() {
+    let item = match thing {
+      Value::Array(ref array) => { array.get(index(name, index_)?) },
+      ref other => { return Err(doesnt_like(name, other)); },
+    };
+    thing = item.ok_or_else(|| doesnt_like(name, index_))?;
+  }
+  Ok(thing)
+}
+
+// An array can't be put inside itself, printing it would never end.
+fn set_item(name: &str, array: &Array, index: i32, value: Value) -> Result<Option<Value>, Unwind> {
+  if contains_array(&value, array) {
+    return Err(doesnt_like(name, &value));
+  }
+  if !array.set(index, value) {
+    return Err(doesnt_like(name, &Value::Num(index as NumType)));
+  }
+  Ok(None)
+}
+
+fn contains_array(thing: &Value, array: &Array) -> bool {
+  match thing {
+    Value::Array(other) => {
+      other == array || other.to_list().iter().any(|item| contains_array(item, array))
+    },
+    Value::List(list) => { list.iter().any(|item| contains_array(item, array)) },
+    _ => { false },
+  }
+}
+
+fn didnt_output(name: &str, to: &str) -> Unwind {
+  ErrorKind::DidntOutput(name.to_string(), to.to_string()).into()
+}
+
+// Name of what didn't output, for didnt_output.
+fn callee_name(ast_node: &AST) -> String {
+  match ast_node {
+    AST::Call(name, _) => { name.clone() },
+    AST::Parens(expr_list) if !expr_list.is_empty() => { callee_name(&expr_list[0]) },
+    other => { format!("{:?}", other) },
+  }
+}
+
+// An input of TO as it was typed in, for doesnt_like.
+fn to_input(arg: &AST) -> Value {
+  match arg {
+    AST::Function(name) | AST::Word(name) => { Value::Word(name.clone()) },
+    AST::Var(var) => { Value::Word(format!(":{}", var)) },
+    AST::Num(num) => { Value::Num(*num) },
+    other => { panic!("Not an input of TO {:?}", other); },
+  }
+}
+
+// Checks the number of inputs to a call from APPLY or INVOKE, the parser can't.
+fn check_inputs(name: &str, arity: &Arity, count: usize) -> Result<(), Unwind> {
+  if count < arity.min {
+    return Err(ErrorKind::NotEnoughInputs(name.to_string()).into());
+  }
+  if arity.max.is_some_and(|max| count > max) {
+    return Err(ErrorKind::TooManyInputs(name.to_string()).into());
+  }
+  Ok(())
+}
+
+fn unused_output(value: Value) -> Unwind {
+  ErrorKind::UnusedOutput(value).into()
+}
+
+// The operator only gets its name written out for the error.
+fn operand_number(operator: &dyn std::fmt::Display, operand: &Value) -> Result<NumType, Unwind> {
+  to_number(operand).ok_or_else(|| doesnt_like(&operator.to_string(), operand))
+}
+
+fn negate(operand: Value) -> Result<Value, Unwind> {
+  Ok(Value::Num(-operand_number(&"-", &operand)?))
+}
+
+fn binary(operator: Operator, left: Value, right: Value) -> Result<Value, Unwind> {
+  let left = operand_number(&operator, &left)?;
+  let right = operand_number(&operator, &right)?;
+  let result = match operator {
+    Operator::Plus => { left + right },
+    Operator::Minus => { left - right },
+    Operator::Multiply => { left * right },
+    Operator::Divide if right == 0.0 => {
+      return Err(ErrorKind::DivideByZero.into());
+    },
+    Operator::Divide => { left / right },
+    Operator::Modulo if right == 0.0 => {
+      return Err(ErrorKind::DivideByZero.into());
+    },
+    Operator::Modulo => { left % right },
+    _ => {
+      panic!("Unknown binary operator {:?}", operator);
+    }
+  };
+  if !result.is_finite() {
+    return Err(ErrorKind::NumberTooBig.into());
+  }
+  Ok(Value::Num(result))
+}
+
+fn comparison(operator: Operator, left: Value, right: Value) -> Result<Value, Unwind> {
+  for word in [&left, &right] {
+    match word {
+      Value::Num(_) | Value::Word(_) => {},
+      other => { return Err(doesnt_like(&operator.to_string(), other)); }
+    }
+  }
+  // Words which look like numbers compare as numbers, BF 100 = 0.
+  let result;
+  if let (Some(left), Some(right)) = (to_number(&left), to_number(&right)) {
+    result = match operator {
+      Operator::Less => { left < right },
+      Operator::LessEq => { left <= right },
+      Operator::Greater => { left > right },
+      Operator::GreaterEq => { left >= right },
+      Operator::Equal => { left == right },
+      _ => {
+        panic!("Unknown comparison operator {:?}", operator);
+      }
+    };
+  } else {
+    let left = to_word(&left).unwrap();
+    let right = to_word(&right).unwrap();
+    result = match operator {
+      Operator::Less => { left < right },
+      Operator::LessEq => { left <= right },
+      Operator::Greater => { left > right },
+      Operator::GreaterEq => { left >= right },
+      Operator::Equal => { left == right },
+      _ => {
+        panic!("Unknown comparison operator {:?}", operator);
+      }
+    };
+  }
+  Ok(bool_word(result))
+}
+
+// (+ 1 2 3) and (* 1 2 3).
+fn nary(operator: Operator, operands: Vec<Value>) -> Result<Value, Unwind> {
+  let mut result = match operator {
+    Operator::Plus => { 0.0 },
+    Operator::Multiply => { 1.0 },
+    _ => {
+      panic!("Unknown prefix operator {:?}", operator);
+    }
+  };
+  for operand in operands {
+    let operand = operand_number(&operator, &operand)?;
+    if operator == Operator::Plus {
+      result += operand;
+    } else {
+      result *= operand;
+    }
+  }
+  Ok(Value::Num(result))
+}
+
+// What's left of an unwind at the top level, as an error.  THROW "TOPLEVEL just stops.
+fn top_level_error(err: Unwind) -> Option<LogoError> {
+  let only_in_procedure = |name: &str| LogoError::from(ErrorKind::OnlyInProcedure(name.to_string()));
+  match err {
+    Unwind::Error(err) => { Some(*err) },
+    Unwind::Stop => { Some(only_in_procedure("STOP")) },
+    Unwind::Output(_) => { Some(only_in_procedure("OUTPUT")) },
+    Unwind::Go(_) => { Some(only_in_procedure("GO")) },
+    Unwind::Throw(throw) if throw.0 == "TOPLEVEL" => { None },
+    Unwind::Throw(throw) => { Some(ErrorKind::NoCatch(throw.0).into()) },
+    Unwind::TailCall(_) => { unreachable!("Tail calls only come from procedure bodies") },
+  }
+}
+
+// What the compiled code does with the output of a call, like eval does for the parsed code.
+fn use_output(name: &str, output: Option<Value>, use_: &Use, stack: &mut Vec<Value>)
+    -> Result<(), Unwind> {
+  match (output, use_) {
+    (Some(value), Use::Instruction) | (Some(value), Use::Tail) => { Err(unused_output(value)) },
+    // A builtin like SUM as the input to OUTPUT, OUTPUT itself wasn't compiled.
+    (Some(value), Use::TailOutput(_)) => { Err(Unwind::Output(value)) },
+    (None, Use::TailOutput(to)) => { Err(didnt_output(name, to)) },
+    (Some(value), _) => {
+      stack.push(value);
+      Ok(())
+    },
+    (None, Use::Input(to)) => { Err(didnt_output(name, to)) },
+    (None, Use::Condition(list)) => { Err(ErrorKind::NotCondition(Value::List(list.clone())).into()) },
+    (None, _) => { Ok(()) },
+  }
+}
+
+// Inputs of a builtin call, evaluated and in order.
+struct Inputs<'a> {
+  name: &'a str,
+  inputs: std::vec::IntoIter<Value>,
+}
+
+impl<'a> Inputs<'a> {
+  fn new(name: &'a str, inputs: Vec<Value>) -> Self {
+    Inputs { name, inputs: inputs.into_iter() }
+  }
+
+  fn has_next(&self) -> bool {
+    self.inputs.len() > 0
+  }
+
+  fn next(&mut self) -> Result<Value, Unwind> {
+    match self.inputs.next() {
+      Some(input) => { Ok(input) },
+      None => { Err(ErrorKind::NotEnoughInputs(self.name.to_string()).into()) },
+    }
+  }
+
+  // All the remaining inputs, for the ones like (LIST 1 2 3) which take any number of them.
+  fn rest(&mut self) -> Vec<Value> {
+    self.inputs.by_ref().collect()
+  }
+
+  fn next_number(&mut self) -> Result<NumType, Unwind> {
+    let thing = self.next()?;
+    to_number(&thing).ok_or_else(|| doesnt_like(self.name, &thing))
+  }
+
+  fn next_int(&mut self) -> Result<i32, Unwind> {
+    let thing = self.next()?;
+    index(self.name, &thing)
+  }
+
+  fn next_array(&mut self) -> Result<Array, Unwind> {
+    match self.next()? {
+      Value::Array(array) => { Ok(array) },
+      other => { Err(doesnt_like(self.name, &other)) },
+    }
+  }
+
+  fn next_list(&mut self) -> Result<List, Unwind> {
+    match self.next()? {
+      Value::List(list) => { Ok(list) },
+      other => { Err(doesnt_like(self.name, &other)) },
+    }
+  }
+
+  fn next_word(&mut self) -> Result<WordType, Unwind> {
+    match self.next()? {
+      Value::Word(word) => { Ok(word) },
+      other => { Err(doesnt_like(self.name, &other)) },
+    }
+  }
+
+  fn next_bool(&mut self) -> Result<bool, Unwind> {
+    let thing = self.next()?;
+    as_bool(&thing).ok_or_else(|| doesnt_like(self.name, &thing))
+  }
+
+  fn rest_bools(&mut self) -> Result<Vec<bool>, Unwind> {
+    let mut bools = Vec::new();
+    while self.has_next() {
+      bools.push(self.next_bool()?);
+    }
+    Ok(bools)
+  }
+}
+
+// Builtins output Some(value), or None for commands like FD.
+type BuiltinFunctionType = dyn Fn(&mut Evaluator, &mut Inputs) -> Result<Option<Value>, Unwind>;
+
+struct Builtin {
+  arity: Arity,
+  function: Rc<BuiltinFunctionType>,
+}
+
+impl Builtin {
+  fn new<F>(arity: Arity, function: F) -> Self
+      where F: Fn(&mut Evaluator, &mut Inputs) -> Result<Option<Value>, Unwind> + 'static {
+    Builtin {
+      arity,
+      function: Rc::new(function),
+    }
+  }
+}
+
+// Procedure calls and the lists that get run are levels of depth.  A level takes up to around 24kB
+// of the Rust stack in a debug build, evaluators run on a thread with the stack for their depth.
+pub const DEFAULT_MAX_DEPTH: usize = 4000;
+const LEVEL_STACK_SIZE: usize = 32 << 10;
+// Below the first level: the line being fed, the builtins and the printing of errors.
+const BASE_STACK_SIZE: usize = 8 << 20;
+// Deeper limits would ask for more address space than there is to spare.
+const MAX_STACK_SIZE: usize = 1 << 30;
+// How many of the calls an error came through get printed, deep recursion would print them all.
+const MAX_CALLERS: usize = 5;
+
+// The Rust stack an evaluator needs to go max_depth deep, None when that's too much to ask for.
+pub fn stack_size(max_depth: usize) -> Option<usize> {
+  let size = max_depth.checked_mul(LEVEL_STACK_SIZE)?.checked_add(BASE_STACK_SIZE)?;
+  if size <= MAX_STACK_SIZE { Some(size) } else { None }
+}
+
+// Spawns a thread with the stack for an evaluator going max_depth deep, f makes and runs it there.
+pub fn spawn<T, F>(max_depth: usize, f: F) -> std::io::Result<std::thread::JoinHandle<T>>
+    where T: Send + 'static, F: FnOnce() -> T + Send + 'static {
+  let size = stack_size(max_depth).ok_or_else(|| {
+    let text = format!("A max depth of {} needs too big a stack", max_depth);
+    std::io::Error::new(std::io::ErrorKind::InvalidInput, text)
+  })?;
+  std::thread::Builder::new().stack_size(size).spawn(f)
+}
 
 pub struct Evaluator {
   parser: parser::Parser,
   turtle: turtle::Turtle,
 
   // Global variables.
-  vars: HashMap<String, AST>,
+  vars: VarsType,
+  // Property lists by their name, the empty ones are left out.
+  plists: HashMap<WordType, PropsType>,
 
   // Function local variables.
-  stack_vars: Vec<HashMap<String, AST>>,
-  // Current expression, (iterator) list.
-  stack_expr: Vec<ListType>,
+  stack_vars: Vec<VarsType>,
+  // Pass counters of the currently running REPEAT and FOREVER loops.
+  stack_repcount: Vec<i32>,
+  // State of the RANDOM number generator.
+  random_state: u64,
 
-  builtin_functions: HashMap<String, std::rc::Rc<BuiltinFunctionType>>,
-  user_functions: HashMap<String, (ArgsType, ListType)>,
+  // Builtins by the index their names (two for the ones like FORWARD and FD) give.
+  builtins: Vec<Builtin>,
+  builtin_functions: HashMap<String, usize>,
+  // User defined procedures by the slot their names give, erasing one leaves its slot empty.  The
+  // compiled calls refer to the slots.
+  user_functions: Vec<Option<UserFunction>>,
+  procedure_slots: HashMap<String, usize>,
+  // Inputs and operands of the running compiled code, shared by all the levels of it.
+  stack_values: Vec<Value>,
+  mode: Mode,
+  // Number of procedure calls in progress, tail calls don't add to it, and the most there can be.
+  depth: usize,
+  max_depth: usize,
+  // Everything printed, when it's being recorded (comparing the modes).
+  printed: Option<Vec<String>>,
+  // How many errors got printed, the examples compared in both modes mustn't have any.
+  errors: usize,
+  // Tags of the running CATCHes, THROW needs one of them.
+  catch_tags: Vec<WordType>,
+  // The error caught by CATCH "ERROR (or handled by ERRACT), until ERROR outputs it.
+  last_error: Option<LogoError>,
+  // Inputs of the running explicit-slot templates (APPLY [? * ?2] [3 4]), for ? and ?2.
+  template_inputs: Vec<Vec<Value>>,
 
-  // Name, args, and lines of the currently defined function.
-  name: String,
-  args: ArgsType,
-  lines: ListType,
+  // The procedure being defined, feed collects its lines.
+  definition: Option<Definition>,
 }
 
 impl Evaluator {
@@ -38,669 +704,2766 @@ impl Evaluator {
     let mut evaluator = Evaluator {
       parser: parser::Parser::new(),
       turtle: turtle::Turtle::new(graphics),
-      vars: HashMap::new(),
+      vars: VarsType::new(),
+      plists: HashMap::new(),
       stack_vars: Vec::new(),
-      stack_expr: Vec::new(),
+      stack_repcount: Vec::new(),
+      random_state: std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)
+                        .map_or(1, |time| time.as_nanos() as u64 | 1),
+      builtins: Vec::new(),
       builtin_functions: HashMap::new(),
-      user_functions: HashMap::new(),
-      name: String::new(),
-      args: ArgsType::new(),
-      lines: ListType::new(),
+      user_functions: Vec::new(),
+      procedure_slots: HashMap::new(),
+      stack_values: Vec::new(),
+      mode: Mode::Compile,
+      depth: 0,
+      max_depth: DEFAULT_MAX_DEPTH,
+      printed: None,
+      errors: 0,
+      catch_tags: Vec::new(),
+      last_error: None,
+      template_inputs: Vec::new(),
+      definition: None,
     };
-    evaluator.stack_vars.push(HashMap::new());
+    evaluator.stack_vars.push(VarsType::new());
     evaluator.define_builtins();
+    for (name, index) in evaluator.builtin_functions.iter() {
+      evaluator.parser.set_arity(name, evaluator.builtins[*index].arity);
+    }
     evaluator
   }
 
-  fn local_vars(&mut self) -> &mut HashMap<String, AST> {
-    self.stack_vars.last_mut().unwrap()
+  pub fn set_mode(&mut self, mode: Mode) {
+    self.mode = mode;
+  }
+
+  // The thread running the evaluator needs the stack_size of it, spawn gives one with that.
+  pub fn set_max_depth(&mut self, max_depth: usize) {
+    self.max_depth = max_depth;
+  }
+
+  fn print(&mut self, text: String) {
+    println!("{}", text);
+    if let Some(printed) = self.printed.as_mut() {
+      printed.push(text);
+    }
+  }
+
+  // An error nothing caught, it goes to the list in the ERRACT variable when there is one, which
+  // can look at it with ERROR.  Otherwise it's printed.
+  fn uncaught(&mut self, err: LogoError) {
+    let erract = match self.vars.get("ERRACT") {
+      Some(Some(Value::List(list))) if !list.is_empty() => { list.clone() },
+      _ => {
+        self.report(&err);
+        return;
+      },
+    };
+    self.last_error = Some(err);
+    if let Err(err) = self.with_stacks(|evaluator| evaluator.eval_list(&erract)) {
+      // Errors in ERRACT itself don't go back to it.
+      if let Some(err) = top_level_error(err) {
+        self.report(&err);
+      }
+    }
+  }
+
+  // Errors print like in PC Logo, followed by the calls they came through.
+  fn report(&mut self, err: &LogoError) {
+    self.errors += 1;
+    self.print(err.to_string());
+    for caller in err.callers.iter().take(MAX_CALLERS) {
+      self.print(format!("  called from {}", caller));
+    }
+    if err.callers.len() > MAX_CALLERS {
+      self.print(format!("  ... and {} more", err.callers.len() - MAX_CALLERS));
+    }
+  }
+
+  fn stacks(&self) -> Stacks {
+    Stacks {
+      vars: self.stack_vars.len(),
+      repcount: self.stack_repcount.len(),
+      catch_tags: self.catch_tags.len(),
+      templates: self.template_inputs.len(),
+      depth: self.depth,
+    }
+  }
+
+  fn restore(&mut self, stacks: Stacks) {
+    self.stack_vars.truncate(stacks.vars);
+    self.stack_repcount.truncate(stacks.repcount);
+    self.catch_tags.truncate(stacks.catch_tags);
+    self.template_inputs.truncate(stacks.templates);
+    self.depth = stacks.depth;
+  }
+
+  // Runs f, and then drops whatever it left on the stacks, whichever way it returned.
+  fn with_stacks<T, F: FnOnce(&mut Evaluator) -> T>(&mut self, f: F) -> T {
+    let stacks = self.stacks();
+    let mut evaluator = guard(&mut *self, move |evaluator| evaluator.restore(stacks));
+    f(&mut evaluator)
+  }
+
+  // Runs f a level deeper, procedure calls and the lists that get run nest on the Rust stack.  Too
+  // deep is a Stack overflow error instead of running out of the Rust stack.
+  fn deeper<T, F>(&mut self, f: F) -> Result<T, Unwind>
+      where F: FnOnce(&mut Evaluator) -> Result<T, Unwind> {
+    if self.depth >= self.max_depth {
+      return Err(ErrorKind::StackOverflow.into());
+    }
+    self.with_stacks(|evaluator| {
+      evaluator.depth += 1;
+      f(evaluator)
+    })
+  }
+
+  fn local_vars(&mut self) -> &mut VarsType {
+    self.stack_vars.last_mut().unwrap()
+  }
+
+  // Logo has dynamic scoping, a procedure sees the local variables of all of its callers.
+  fn get(&self, var: &str) -> Result<Value, Unwind> {
+    let value = self.stack_vars.iter().rev().find_map(|local_vars| local_vars.get(var))
+                    .or_else(|| self.vars.get(var));
+    match value {
+      Some(Some(value)) => { Ok(value.clone()) },
+      Some(None) => { Err(ErrorKind::NoValue(var.to_string()).into()) },
+      None => { Err(ErrorKind::NotAName(var.to_string()).into()) },
+    }
+  }
+
+  fn set(&mut self, var: WordType, value: Value) {
+    match self.stack_vars.iter_mut().rev().find(|local_vars| local_vars.contains_key(&var)) {
+      Some(local_vars) => { local_vars.insert(var, Some(value)); },
+      None => { self.vars.insert(var, Some(value)); },
+    }
+  }
+
+  // Creates the variable in the current procedure, without a value (None) until it's set.
+  fn set_local(&mut self, var: WordType, value: Option<Value>) -> Result<(), Unwind> {
+    if self.stack_vars.len() == 1 {
+      return Err(ErrorKind::OnlyInProcedure("LOCAL".to_string()).into());
+    }
+    self.local_vars().insert(var, value);
+    Ok(())
+  }
+
+  // The names of the builtin give its index.
+  fn insert_builtin(&mut self, names: &[String], builtin: Builtin) {
+    for name in names {
+      self.builtin_functions.insert(name.clone(), self.builtins.len());
+    }
+    self.builtins.push(builtin);
+  }
+
+  fn builtin(&self, name: &str) -> Option<&Builtin> {
+    self.builtin_functions.get(name).map(|&index| &self.builtins[index])
+  }
+
+  fn user_function(&self, name: &str) -> Option<&UserFunction> {
+    self.procedure_slots.get(name).and_then(|&slot| self.user_functions[slot].as_ref())
+  }
+
+  // What the name calls, the slot of a user defined procedure stays when it's erased.
+  fn callee(&self, name: &str) -> Option<Callee> {
+    match self.builtin_functions.get(name) {
+      Some(&index) => { Some(Callee::Builtin(index)) },
+      None => { self.procedure_slots.get(name).map(|&slot| Callee::User(slot)) },
+    }
+  }
+
+  fn define_builtins(&mut self) {
+    #![allow(unused_parens)]
+    macro_rules! builtin_name {
+      ($name:ident) => { stringify!($name).to_string() };
+      // For names which aren't valid identifiers (DO.WHILE).
+      ($name:literal) => { $name.to_string() };
+    }
+    macro_rules! add_builtin {
+      ($name1:tt, $name2:tt, $arity:expr, $closure:tt) => {
+        let builtin = Builtin::new($arity, $closure);
+        self.insert_builtin(&[builtin_name!($name1), builtin_name!($name2)], builtin);
+      };
+      ($name:tt, $arity:expr, $closure:tt) => {
+        self.insert_builtin(&[builtin_name!($name)], Builtin::new($arity, $closure));
+      };
+    }
+    macro_rules! add_direct_builtin {
+      ($($name:tt),+ ; $func:ident) => {
+        add_builtin!($($name),+, Arity::fixed(0), (|evaluator: &mut Evaluator, _inputs: &mut Inputs| {
+          evaluator.turtle.$func();
+          Ok(None)
+        }));
+      };
+    }
+    macro_rules! add_direct_builtin_num {
+      ($($name:tt),+ ; $func:ident) => {
+        add_builtin!($($name),+, Arity::fixed(1), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+          let num = inputs.next_number()?;
+          evaluator.turtle.$func(num);
+          Ok(None)
+        }));
+      };
+    }
+    macro_rules! add_math_builtin {
+      ($name:ident, $func:expr) => {
+        add_builtin!($name, Arity::fixed(1), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+          let num = inputs.next_number()?;
+          checked_num(stringify!($name), num, $func(num))
+        }));
+      };
+    }
+    // The second function tells which input (0 or 1) an error is about.
+    macro_rules! add_math_builtin2 {
+      ($name:ident, $func:expr, $bad_input:expr) => {
+        add_builtin!($name, Arity::fixed(2), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+          let left = inputs.next_number()?;
+          let right = inputs.next_number()?;
+          let bad_input = [left, right][$bad_input(left, right)];
+          checked_num(stringify!($name), bad_input, $func(left, right))
+        }));
+      };
+    }
+    macro_rules! add_direct_builtin_ret_num {
+      ($($name:tt),+ ; $func:ident) => {
+        add_builtin!($($name),+, Arity::fixed(0), (|evaluator: &mut Evaluator, _inputs: &mut Inputs| {
+          let num = evaluator.turtle.$func();
+          Ok(Some(Value::Num(num)))
+        }));
+      };
+    }
+    add_builtin!(PO, Arity::fixed(1), (|evaluator, inputs| {
+      // PO "PROC, PO [PROC1 PROC2] or PO [[procedures] [variables] [property lists]].
+      let thing = inputs.next()?;
+      let contents = contents("PO", &thing)?;
+      for line in evaluator.contents_source("PO", &contents)? {
+        evaluator.print(line);
+      }
+      Ok(None)
+    }));
+    add_builtin!(POTS, Arity::fixed(0), (|evaluator, _inputs| {
+      for name in evaluator.procedure_names() {
+        let title = evaluator.procedure_title(&name);
+        evaluator.print(title);
+      }
+      Ok(None)
+    }));
+    add_builtin!(POPS, Arity::fixed(0), (|evaluator, _inputs| {
+      for name in evaluator.procedure_names() {
+        for line in evaluator.procedure_source(&name) {
+          evaluator.print(line);
+        }
+      }
+      Ok(None)
+    }));
+    add_builtin!(POALL, Arity::fixed(0), (|evaluator, _inputs| {
+      for line in evaluator.workspace_source() {
+        evaluator.print(line);
+      }
+      Ok(None)
+    }));
+    add_builtin!(ERASE, ER, Arity::fixed(1), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // Takes the same as PO.
+      let thing = inputs.next()?;
+      let contents = contents("ERASE", &thing)?;
+      evaluator.erase("ERASE", &contents)
+    }));
+    add_builtin!(ERPS, Arity::fixed(0), (|evaluator, _inputs| {
+      let procedures = evaluator.procedure_names();
+      evaluator.erase("ERPS", &Contents { procedures, vars: Vec::new(), plists: Vec::new() })
+    }));
+    add_builtin!(ERPLS, Arity::fixed(0), (|evaluator, _inputs| {
+      evaluator.plists.clear();
+      Ok(None)
+    }));
+    add_builtin!(ERALL, Arity::fixed(0), (|evaluator, _inputs| {
+      let procedures = evaluator.procedure_names();
+      evaluator.erase("ERALL", &Contents { procedures, vars: Vec::new(), plists: Vec::new() })?;
+      evaluator.vars.clear();
+      evaluator.plists.clear();
+      Ok(None)
+    }));
+    add_builtin!(PONS, Arity::fixed(0), (|evaluator, _inputs| {
+      // The variables as the instructions that make them, the running procedure's first.
+      for line in evaluator.locals_source().into_iter().chain(evaluator.names_source()) {
+        evaluator.print(line);
+      }
+      Ok(None)
+    }));
+    add_builtin!(PR, PRINT, Arity::new(1, 0, None), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let texts: Vec<String> = inputs.rest().iter().map(|input| to_text(input, false)).collect();
+      evaluator.print(texts.join(" "));
+      Ok(None)
+    }));
+    add_builtin!(OP, OUTPUT, Arity::fixed(1), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      Err(Unwind::Output(inputs.next()?))
+    }));
+    add_builtin!(STOP, Arity::fixed(0), (|_evaluator, _inputs| {
+      Err(Unwind::Stop)
+    }));
+    add_builtin!(LABEL, Arity::fixed(1), (|_evaluator, inputs| {
+      // Only marks the line for GO.
+      inputs.next_word()?;
+      Ok(None)
+    }));
+    add_builtin!(GO, Arity::fixed(1), (|_evaluator, inputs| {
+      Err(Unwind::Go(inputs.next_word()?))
+    }));
+    add_builtin!(CATCH, Arity::fixed(2), (|evaluator, inputs| {
+      // CATCH "TAG [list] outputs what THROW "TAG throws, CATCH "ERROR catches the errors.
+      let tag = inputs.next_word()?;
+      let list = inputs.next_list()?;
+      let ret = evaluator.with_stacks(|evaluator| {
+        evaluator.catch_tags.push(tag.clone());
+        evaluator.eval_list_value(&list)
+      });
+      match ret {
+        Err(Unwind::Throw(throw)) if throw.0 == tag => { Ok(throw.1) },
+        Err(Unwind::Error(err)) if tag == "ERROR" => {
+          evaluator.last_error = Some(*err);
+          Ok(None)
+        },
+        other => { other },
+      }
+    }));
+    add_builtin!(THROW, Arity::new(1, 1, Some(2)), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // THROW "TAG or (THROW "TAG value).  THROW "ERROR is an error, THROW "TOPLEVEL stops
+      // everything.
+      let tag = inputs.next_word()?;
+      let value = if inputs.has_next() { Some(inputs.next()?) } else { None };
+      if tag == "ERROR" {
+        return Err(ErrorKind::Thrown(value).into());
+      }
+      if tag != "TOPLEVEL" && !evaluator.catch_tags.contains(&tag) {
+        return Err(ErrorKind::NoCatch(tag).into());
+      }
+      Err(Unwind::Throw(Box::new((tag, value))))
+    }));
+    add_builtin!(ERROR, Arity::fixed(0), (|evaluator, _inputs| {
+      // [code message procedure line] of the last caught error, or [] if there's none.
+      let err = match evaluator.last_error.take() {
+        Some(err) => { err },
+        None => { return Ok(Some(Value::List(List::new()))); },
+      };
+      let code = Value::Num(err.code() as NumType);
+      let message = Value::Word(err.kind.to_string());
+      let (procedure, line) = match err.place {
+        Some(Place { procedure, line }) => {
+          (Value::Word(procedure), line.map_or(Value::List(List::new()), Value::Word))
+        },
+        None => { (Value::List(List::new()), Value::List(List::new())) },
+      };
+      Ok(Some(Value::List(List::from(vec![code, message, procedure, line]))))
+    }));
+
+    add_builtin!(RUN, Arity::fixed(1), (|evaluator, inputs| {
+      // Outputs what the list outputs, if anything (PR RUN [1 + 2]).
+      let list = inputs.next_list()?;
+      evaluator.eval_list_value(&list)
+    }));
+    add_builtin!(RUNRESULT, Arity::fixed(1), (|evaluator, inputs| {
+      // [] if the list doesn't output anything, [value] if it does.
+      let list = inputs.next_list()?;
+      let result = evaluator.eval_list_value(&list)?;
+      Ok(Some(Value::List(result.into_iter().collect())))
+    }));
+    add_builtin!(APPLY, Arity::fixed(2), (|evaluator, inputs| {
+      let template = inputs.next()?;
+      let list = inputs.next_list()?;
+      evaluator.apply("APPLY", &template, list.iter().cloned().collect())
+    }));
+    add_builtin!(INVOKE, Arity::new(2, 1, None), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // INVOKE template input, or (INVOKE template input1 input2 ...).
+      let template = inputs.next()?;
+      evaluator.apply("INVOKE", &template, inputs.rest())
+    }));
+    add_builtin!(MAP, Arity::new(2, 2, None), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // MAP template data, or (MAP template data1 data2 ...) going through them side by side.
+      // Outputs a list of the outputs, or a word when the data is a word.
+      let template = inputs.next()?;
+      let data = inputs.rest();
+      let mut outputs = Vec::new();
+      for (i, row) in zip_items("MAP", &data)?.into_iter().enumerate() {
+        outputs.push(evaluator.apply_output("MAP", &template, row, i + 1)?);
+      }
+      Ok(Some(from_items("MAP", &data[0], outputs)?))
+    }));
+    add_builtin!(FILTER, Arity::fixed(2), (|evaluator, inputs| {
+      let template = inputs.next()?;
+      let data = inputs.next()?;
+      let mut kept = Vec::new();
+      for (i, item) in items("FILTER", &data)?.into_iter().enumerate() {
+        if evaluator.apply_bool("FILTER", &template, vec![item.clone()], i + 1)? {
+          kept.push(item);
+        }
+      }
+      Ok(Some(from_items("FILTER", &data, kept)?))
+    }));
+    add_builtin!(FIND, Arity::fixed(2), (|evaluator, inputs| {
+      // The first item the template outputs TRUE for, [] if there's none.
+      let template = inputs.next()?;
+      let data = inputs.next()?;
+      for (i, item) in items("FIND", &data)?.into_iter().enumerate() {
+        if evaluator.apply_bool("FIND", &template, vec![item.clone()], i + 1)? {
+          return Ok(Some(item));
+        }
+      }
+      Ok(Some(Value::List(List::new())))
+    }));
+    add_builtin!(FOREACH, Arity::new(2, 2, None), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // FOREACH data template, or (FOREACH data1 data2 ... template).
+      let mut data = inputs.rest();
+      let template = data.pop().unwrap();
+      for (i, row) in zip_items("FOREACH", &data)?.into_iter().enumerate() {
+        if let Some(value) = evaluator.apply_counted("FOREACH", &template, row, i + 1)? {
+          return Err(unused_output(value));
+        }
+      }
+      Ok(None)
+    }));
+    add_builtin!(REDUCE, Arity::fixed(2), (|evaluator, inputs| {
+      // REDUCE [?1 + ?2] [1 2 3] is 1 + (2 + 3), one item is the output as it is.
+      let template = inputs.next()?;
+      let data = inputs.next()?;
+      let mut items = items("REDUCE", &data)?;
+      let mut result = items.pop().ok_or_else(|| doesnt_like("REDUCE", &data))?;
+      for (i, item) in items.into_iter().rev().enumerate() {
+        result = evaluator.apply_output("REDUCE", &template, vec![item, result], i + 1)?;
+      }
+      Ok(Some(result))
+    }));
+    add_builtin!(CROSSMAP, Arity::new(2, 2, None), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // CROSSMAP template [data1 data2], or (CROSSMAP template data1 data2 ...), with every
+      // combination of the items.
+      let template = inputs.next()?;
+      let mut data = inputs.rest();
+      if data.len() == 1 {
+        data = items("CROSSMAP", &data[0])?;
+      }
+      let mut outputs = Vec::new();
+      for (i, row) in cross_items("CROSSMAP", &data)?.into_iter().enumerate() {
+        outputs.push(evaluator.apply_output("CROSSMAP", &template, row, i + 1)?);
+      }
+      Ok(Some(Value::List(List::from(outputs))))
+    }));
+    add_builtin!(CASCADE, Arity::new(3, 3, None), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // CASCADE endtest template start, the template makes the next value out of ? until endtest
+      // (a number of times, or a template) is done.  (CASCADE endtest template1 start1 template2
+      // start2 ... finaltemplate) goes through more values at once, each template gets all of
+      // them as ?1, ?2, ...  Outputs the first value, or what finaltemplate makes of them.
+      let endtest = inputs.next()?;
+      let mut rest = inputs.rest();
+      let finish = if rest.len() % 2 == 1 { rest.pop() } else { None };
+      if rest.is_empty() {
+        return Err(ErrorKind::NotEnoughInputs("CASCADE".to_string()).into());
+      }
+      let templates: Vec<Value> = rest.iter().step_by(2).cloned().collect();
+      let mut values: Vec<Value> = rest.iter().skip(1).step_by(2).cloned().collect();
+      let mut count = 1;
+      loop {
+        let done = match to_number(&endtest) {
+          Some(times) => { count as NumType > times },
+          None => { evaluator.apply_bool("CASCADE", &endtest, values.clone(), count)? },
+        };
+        if done {
+          break;
+        }
+        let mut next = Vec::new();
+        for template in templates.iter() {
+          next.push(evaluator.apply_output("CASCADE", template, values.clone(), count)?);
+        }
+        values = next;
+        count += 1;
+      }
+      match finish {
+        Some(finish) => { Ok(Some(evaluator.apply_output("CASCADE", &finish, values, count)?)) },
+        None => { Ok(Some(values.swap_remove(0))) },
+      }
+    }));
+    add_builtin!("?", Arity::new(0, 0, Some(1)), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // ? or (? 2), the same as ?2.
+      let index = if inputs.has_next() { inputs.next_number()? } else { 1.0 };
+      evaluator.slot("?", index as usize)
+    }));
+    for index in 1 ..= 9 {
+      let name = format!("?{}", index);
+      let slot = name.clone();
+      let builtin = Builtin::new(Arity::fixed(0), move |evaluator, _inputs| {
+        evaluator.slot(&slot, index)
+      });
+      self.insert_builtin(&[name], builtin);
+    }
+
+    add_builtin!(LOAD, Arity::fixed(1), (|evaluator, inputs| {
+      let file_name = file_name(&inputs.next_word()?);
+      evaluator.load(&file_name)?;
+      Ok(None)
+    }));
+    add_builtin!(SAVE, Arity::fixed(1), (|evaluator, inputs| {
+      // Everything POALL prints, LOAD gets the same procedures, variables and property lists back.
+      let file_name = file_name(&inputs.next_word()?);
+      evaluator.save(&file_name, &evaluator.workspace_source())?;
+      Ok(None)
+    }));
+    add_builtin!(SAVEL, Arity::fixed(2), (|evaluator, inputs| {
+      // SAVEL contents "FILE, what PO prints for the contents.
+      let thing = inputs.next()?;
+      let contents = contents("SAVEL", &thing)?;
+      let file_name = file_name(&inputs.next_word()?);
+      evaluator.save(&file_name, &evaluator.contents_source("SAVEL", &contents)?)?;
+      Ok(None)
+    }));
+
+    add_builtin!(MAKE, Arity::fixed(2), (|evaluator, inputs| {
+      let var = inputs.next_word()?;
+      let value = inputs.next()?;
+      evaluator.set(var, value);
+      Ok(None)
+    }));
+    add_builtin!(NAME, Arity::fixed(2), (|evaluator, inputs| {
+      // MAKE with the inputs the other way around, NAME 5 "A.
+      let value = inputs.next()?;
+      let var = inputs.next_word()?;
+      evaluator.set(var, value);
+      Ok(None)
+    }));
+    add_builtin!(THING, Arity::fixed(1), (|evaluator, inputs| {
+      // THING "A is :A, THING :NAME the variable named by the value of NAME.
+      let var = inputs.next_word()?;
+      evaluator.get(&var).map(Some)
+    }));
+    add_builtin!("NAME?", NAMEP, Arity::fixed(1), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // Only the variables with a value, LOCAL "A alone doesn't give it one.
+      let var = inputs.next_word()?;
+      Ok(Some(bool_word(evaluator.get(&var).is_ok())))
+    }));
+    add_builtin!(NAMES, Arity::fixed(0), (|evaluator, _inputs| {
+      // The global variables as a contents list, [[] [A B]], the first list is for procedures.
+      let vars = evaluator.global_names().into_iter().map(Value::Word).collect();
+      Ok(Some(Value::List(List::from(vec![Value::List(List::new()), Value::List(vars)]))))
+    }));
+    add_builtin!(ERN, Arity::fixed(1), (|evaluator, inputs| {
+      // ERN "A or ERN [A B].
+      let names = inputs.next()?;
+      for var in words("ERN", &names)? {
+        evaluator.erase_var(&var);
+      }
+      Ok(None)
+    }));
+    add_builtin!(ERNS, Arity::fixed(0), (|evaluator, _inputs| {
+      evaluator.vars.clear();
+      Ok(None)
+    }));
+    add_builtin!(PPROP, Arity::fixed(3), (|evaluator, inputs| {
+      // PPROP "PLIST "PROP value, replaces the value if the property is there already.
+      let name = inputs.next_word()?;
+      let prop = inputs.next_word()?;
+      let value = inputs.next()?;
+      let props = evaluator.plists.entry(name).or_insert_with(PropsType::new);
+      match props.iter_mut().find(|(other, _)| *other == prop) {
+        Some(pair) => { pair.1 = value; },
+        None => { props.push((prop, value)); },
+      }
+      Ok(None)
+    }));
+    add_builtin!(GPROP, Arity::fixed(2), (|evaluator, inputs| {
+      // [] when there's no such property.
+      let name = inputs.next_word()?;
+      let prop = inputs.next_word()?;
+      let value = evaluator.plists.get(&name)
+          .and_then(|props| props.iter().find(|(other, _)| *other == prop))
+          .map_or(Value::List(List::new()), |(_, value)| value.clone());
+      Ok(Some(value))
+    }));
+    add_builtin!(REMPROP, Arity::fixed(2), (|evaluator, inputs| {
+      let name = inputs.next_word()?;
+      let prop = inputs.next_word()?;
+      if let Some(props) = evaluator.plists.get_mut(&name) {
+        props.retain(|(other, _)| *other != prop);
+        if props.is_empty() {
+          evaluator.plists.remove(&name);
+        }
+      }
+      Ok(None)
+    }));
+    add_builtin!(PLIST, Arity::fixed(1), (|evaluator, inputs| {
+      // [PROP1 value1 PROP2 value2 ...]
+      let name = inputs.next_word()?;
+      let items = evaluator.plists.get(&name).map_or(Vec::new(), |props| {
+        props.iter().flat_map(|(prop, value)| vec![Value::Word(prop.clone()), value.clone()])
+            .collect()
+      });
+      Ok(Some(Value::List(List::from(items))))
+    }));
+    add_builtin!(PPS, Arity::fixed(0), (|evaluator, _inputs| {
+      // The property lists as the PPROPs that make them.
+      for line in evaluator.plist_source() {
+        evaluator.print(line);
+      }
+      Ok(None)
+    }));
+    add_builtin!(LOCAL, Arity::new(1, 1, None), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // LOCAL "A, LOCAL [A B] or (LOCAL "A "B "C).
+      let mut vars = Vec::new();
+      for input in inputs.rest() {
+        match input {
+          Value::Word(word) => { vars.push(word); },
+          Value::List(list) => {
+            for item in list.iter() {
+              match item {
+                Value::Word(word) => { vars.push(word.clone()); },
+                other => { return Err(doesnt_like("LOCAL", other)); }
+              }
+            }
+          },
+          other => { return Err(doesnt_like("LOCAL", &other)); }
+        }
+      }
+      for var in vars {
+        evaluator.set_local(var, None)?;
+      }
+      Ok(None)
+    }));
+    add_builtin!(LOCALMAKE, Arity::fixed(2), (|evaluator, inputs| {
+      let var = inputs.next_word()?;
+      let value = inputs.next()?;
+      evaluator.set_local(var, Some(value))?;
+      Ok(None)
+    }));
+    add_builtin!(FIRST, Arity::fixed(1), (|_evaluator, inputs| {
+      let thing = inputs.next()?;
+      split_first(&thing).map(|(first, _)| Some(first)).ok_or_else(|| doesnt_like("FIRST", &thing))
+    }));
+    add_builtin!(BUTFIRST, BF, Arity::fixed(1), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let thing = inputs.next()?;
+      split_first(&thing).map(|(_, rest)| Some(rest)).ok_or_else(|| doesnt_like("BUTFIRST", &thing))
+    }));
+    add_builtin!(LAST, Arity::fixed(1), (|_evaluator, inputs| {
+      let thing = inputs.next()?;
+      split_last(&thing).map(|(last, _)| Some(last)).ok_or_else(|| doesnt_like("LAST", &thing))
+    }));
+    add_builtin!(BUTLAST, BL, Arity::fixed(1), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let thing = inputs.next()?;
+      split_last(&thing).map(|(_, rest)| Some(rest)).ok_or_else(|| doesnt_like("BUTLAST", &thing))
+    }));
+    add_builtin!(FPUT, Arity::fixed(2), (|_evaluator, inputs| {
+      // FPUT thing list, or FPUT letter word.
+      let thing = inputs.next()?;
+      match inputs.next()? {
+        Value::List(list) => { Ok(Some(Value::List(list.fput(thing)))) },
+        other => {
+          match (to_word(&thing), to_word(&other)) {
+            (Some(letter), Some(word)) => { Ok(Some(Value::Word(letter + &word))) },
+            _ => { Err(doesnt_like("FPUT", &other)) },
+          }
+        },
+      }
+    }));
+    add_builtin!(LPUT, Arity::fixed(2), (|_evaluator, inputs| {
+      // LPUT thing list, or LPUT letter word.
+      let thing = inputs.next()?;
+      match inputs.next()? {
+        Value::List(list) => { Ok(Some(Value::List(list.lput(thing)))) },
+        other => {
+          match (to_word(&thing), to_word(&other)) {
+            (Some(letter), Some(word)) => { Ok(Some(Value::Word(word + &letter))) },
+            _ => { Err(doesnt_like("LPUT", &other)) },
+          }
+        },
+      }
+    }));
+    add_builtin!(ITEM, Arity::fixed(2), (|_evaluator, inputs| {
+      // ITEM num list/word/num/array, arrays count from their origin.
+      let index = inputs.next_int()?;
+      let thing = inputs.next()?;
+      let item = match thing {
+        Value::List(ref list) => {
+          list.get((index as usize).wrapping_sub(1)).cloned()
+        },
+        Value::Array(ref array) => {
+          array.get(index)
+        },
+        _ => {
+          let word = to_word(&thing).ok_or_else(|| doesnt_like("ITEM", &thing))?;
+          word.chars().nth((index as usize).wrapping_sub(1)).map(|c| Value::Word(c.to_string()))
+        },
+      };
+      item.map(Some).ok_or_else(|| doesnt_like("ITEM", &Value::Num(index as NumType)))
+    }));
+    add_builtin!(COUNT, Arity::fixed(1), (|_evaluator, inputs| {
+      let thing = inputs.next()?;
+      let count = match thing {
+        Value::List(ref list) => { list.len() },
+        Value::Array(ref array) => { array.len() },
+        _ => { to_word(&thing).ok_or_else(|| doesnt_like("COUNT", &thing))?.chars().count() },
+      };
+      Ok(Some(Value::Num(count as NumType)))
+    }));
+    add_builtin!(SENTENCE, SE, Arity::new(2, 0, None), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // Like LIST, except the items of list inputs get spliced into the output.
+      let mut sentence = Vec::new();
+      for input in inputs.rest() {
+        match input {
+          Value::List(list) => { sentence.extend(list.iter().cloned()); },
+          other => { sentence.push(other); },
+        }
+      }
+      Ok(Some(Value::List(List::from(sentence))))
+    }));
+    add_builtin!(WORD, Arity::new(2, 0, None), (|_evaluator, inputs| {
+      let mut word = String::new();
+      for thing in inputs.rest() {
+        word += &to_word(&thing).ok_or_else(|| doesnt_like("WORD", &thing))?;
+      }
+      Ok(Some(Value::Word(word)))
+    }));
+    add_builtin!("EMPTY?", EMPTYP, Arity::fixed(1), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let empty = match inputs.next()? {
+        Value::List(list) => { list.is_empty() },
+        Value::Word(word) => { word.is_empty() },
+        _ => { false },
+      };
+      Ok(Some(bool_word(empty)))
+    }));
+    add_builtin!("LIST?", LISTP, Arity::fixed(1), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let thing = inputs.next()?;
+      Ok(Some(bool_word(matches!(thing, Value::List(_)))))
+    }));
+    add_builtin!("WORD?", WORDP, Arity::fixed(1), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let thing = inputs.next()?;
+      Ok(Some(bool_word(to_word(&thing).is_some())))
+    }));
+    add_builtin!("NUMBER?", NUMBERP, Arity::fixed(1), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let thing = inputs.next()?;
+      Ok(Some(bool_word(to_number(&thing).is_some())))
+    }));
+    add_builtin!("MEMBER?", MEMBERP, Arity::fixed(2), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // MEMBER? thing list, or MEMBER? letters word.
+      let thing = inputs.next()?;
+      let member = match inputs.next()? {
+        Value::List(list) => { list.iter().any(|item| values_equal(&thing, item)) },
+        other => {
+          match (to_word(&thing), to_word(&other)) {
+            (Some(letters), Some(word)) => { !letters.is_empty() && word.contains(&letters) },
+            _ => { false },
+          }
+        },
+      };
+      Ok(Some(bool_word(member)))
+    }));
+    add_builtin!("EQUAL?", EQUALP, Arity::fixed(2), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let left = inputs.next()?;
+      let right = inputs.next()?;
+      Ok(Some(bool_word(values_equal(&left, &right))))
+    }));
+    add_builtin!(LIST, Arity::new(2, 0, None), (|_evaluator, inputs| {
+      // LIST word/list1 word/list2 or (LIST ...), the inputs are already evaluated.
+      Ok(Some(Value::List(List::from(inputs.rest()))))
+    }));
+
+    add_builtin!(ARRAY, Arity::new(1, 1, Some(2)), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // ARRAY size, or (ARRAY size origin), the items start out as empty lists.
+      let size = inputs.next_int()?;
+      let origin = if inputs.has_next() { inputs.next_int()? } else { 1 };
+      if size < 0 {
+        return Err(doesnt_like("ARRAY", &Value::Num(size as NumType)));
+      }
+      Ok(Some(Value::Array(Array::new(vec![Value::List(List::new()); size as usize], origin))))
+    }));
+    add_builtin!(MDARRAY, Arity::new(1, 1, Some(2)), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // MDARRAY [2 3] is an array of 2 arrays of 3 items.
+      let size_list = inputs.next_list()?;
+      let origin = if inputs.has_next() { inputs.next_int()? } else { 1 };
+      let mut sizes = Vec::new();
+      for size in size_list.iter() {
+        match index("MDARRAY", size)? {
+          size if size >= 0 => { sizes.push(size as usize); },
+          _ => { return Err(doesnt_like("MDARRAY", size)); },
+        }
+      }
+      if sizes.is_empty() {
+        return Err(doesnt_like("MDARRAY", &Value::List(size_list)));
+      }
+      Ok(Some(md_array(&sizes, origin)))
+    }));
+    add_builtin!(SETITEM, Arity::fixed(3), (|_evaluator, inputs| {
+      let index = inputs.next_int()?;
+      let array = inputs.next_array()?;
+      let value = inputs.next()?;
+      set_item("SETITEM", &array, index, value)
+    }));
+    add_builtin!(MDITEM, Arity::fixed(2), (|_evaluator, inputs| {
+      // MDITEM [2 3] array is ITEM 3 ITEM 2 array.
+      let indexes = inputs.next_list()?;
+      let array = inputs.next_array()?;
+      md_item("MDITEM", &indexes, Value::Array(array)).map(Some)
+    }));
+    add_builtin!(MDSETITEM, Arity::fixed(3), (|_evaluator, inputs| {
+      let indexes = inputs.next_list()?;
+      let array = inputs.next_array()?;
+      let value = inputs.next()?;
+      let (last, indexes) = match (indexes.last(), indexes.butlast()) {
+        (Some(last), Some(indexes)) => { (last.clone(), indexes) },
+        _ => { return Err(doesnt_like("MDSETITEM", &Value::List(indexes))); },
+      };
+      match md_item("MDSETITEM", &indexes, Value::Array(array))? {
+        Value::Array(array) => { set_item("MDSETITEM", &array, index("MDSETITEM", &last)?, value) },
+        other => { Err(doesnt_like("MDSETITEM", &other)) },
+      }
+    }));
+    add_builtin!(LISTTOARRAY, Arity::new(1, 1, Some(2)), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let list = inputs.next_list()?;
+      let origin = if inputs.has_next() { inputs.next_int()? } else { 1 };
+      Ok(Some(Value::Array(Array::new(list.iter().cloned().collect(), origin))))
+    }));
+    add_builtin!(ARRAYTOLIST, Arity::fixed(1), (|_evaluator, inputs| {
+      Ok(Some(Value::List(inputs.next_array()?.to_list())))
+    }));
+    add_builtin!("ARRAY?", ARRAYP, Arity::fixed(1), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let thing = inputs.next()?;
+      Ok(Some(bool_word(matches!(thing, Value::Array(_)))))
+    }));
+
+    // Trigonometry works in degrees, like the turtle.
+    add_math_builtin!(SQRT, |x: NumType| x.sqrt());
+    add_math_builtin!(EXP, |x: NumType| x.exp());
+    add_math_builtin!(LN, |x: NumType| if x > 0.0 { x.ln() } else { NumType::NAN });
+    add_math_builtin!(LOG10, |x: NumType| if x > 0.0 { x.log10() } else { NumType::NAN });
+    add_math_builtin!(SIN, |x: NumType| x.to_radians().sin());
+    add_math_builtin!(COS, |x: NumType| x.to_radians().cos());
+    // The odd multiples of 90 have no tangent, their radians are just off and give a huge number.
+    add_math_builtin!(TAN, |x: NumType| {
+      if x.rem_euclid(180.0) == 90.0 { NumType::NAN } else { x.to_radians().tan() }
+    });
+    add_math_builtin!(ARCTAN, |x: NumType| x.atan().to_degrees());
+    add_math_builtin!(INT, |x: NumType| x.trunc());
+    add_math_builtin!(ROUND, |x: NumType| x.round());
+    add_math_builtin!(ABS, |x: NumType| x.abs());
+    // A negative or zero base is the bad input, otherwise the exponent is too big.
+    add_math_builtin2!(POWER, |x: NumType, y: NumType| x.powf(y),
+                       |x: NumType, _y: NumType| if x <= 0.0 { 0 } else { 1 });
+    // REMAINDER has the sign of the dividend, MODULO the sign of the divisor.
+    add_math_builtin2!(REMAINDER, |x: NumType, y: NumType| x % y, |_x: NumType, _y: NumType| 1);
+    add_math_builtin2!(MODULO, |x: NumType, y: NumType| ((x % y) + y) % y, |_x: NumType, _y: NumType| 1);
+    add_builtin!(PI, Arity::fixed(0), (|_evaluator, _inputs| {
+      Ok(Some(Value::Num(std::f32::consts::PI)))
+    }));
+    add_builtin!(RANDOM, Arity::fixed(1), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      // RANDOM num outputs a whole number between 0 and num - 1.
+      let num = inputs.next_number()?.trunc();
+      if num < 1.0 {
+        return Err(doesnt_like("RANDOM", &Value::Num(num)));
+      }
+      let random = evaluator.next_random() % num as u64;
+      Ok(Some(Value::Num(random as NumType)))
+    }));
+
+    add_builtin!(AND, Arity::new(2, 0, None), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let inputs = inputs.rest_bools()?;
+      Ok(Some(bool_word(inputs.iter().all(|&input| input))))
+    }));
+    add_builtin!(OR, Arity::new(2, 0, None), (|_evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let inputs = inputs.rest_bools()?;
+      Ok(Some(bool_word(inputs.iter().any(|&input| input))))
+    }));
+    add_builtin!(NOT, Arity::fixed(1), (|_evaluator, inputs| {
+      let input = inputs.next_bool()?;
+      Ok(Some(bool_word(!input)))
+    }));
+
+    add_builtin!(IF, Arity::new(2, 2, Some(3)), (|evaluator, inputs| {
+      // IF cond [list], IF cond [list] [list] or PC Logo's IF cond THEN instr ELSE instr, where
+      // the parser turns bare instructions after THEN and ELSE into lists too.
+      let cond = inputs.next_bool()?;
+      let then_list = inputs.next_list()?;
+      let else_list = if inputs.has_next() { Some(inputs.next_list()?) } else { None };
+      if cond {
+        evaluator.eval_list_value(&then_list)
+      } else if let Some(else_list) = else_list {
+        evaluator.eval_list_value(&else_list)
+      } else {
+        Ok(None)
+      }
+    }));
+    add_builtin!(IFELSE, Arity::fixed(3), (|evaluator, inputs| {
+      let cond = inputs.next_bool()?;
+      let then_list = inputs.next_list()?;
+      let else_list = inputs.next_list()?;
+      evaluator.eval_list_value(if cond { &then_list } else { &else_list })
+    }));
+
+    add_builtin!(WHILE, Arity::fixed(2), (|evaluator, inputs| {
+      let cond = inputs.next_list()?;
+      let list = inputs.next_list()?;
+      while evaluator.eval_list_bool(&cond)? {
+        evaluator.eval_list(&list)?;
+      }
+      Ok(None)
+    }));
+    add_builtin!(UNTIL, Arity::fixed(2), (|evaluator, inputs| {
+      let cond = inputs.next_list()?;
+      let list = inputs.next_list()?;
+      while !evaluator.eval_list_bool(&cond)? {
+        evaluator.eval_list(&list)?;
+      }
+      Ok(None)
+    }));
+    add_builtin!("DO.WHILE", Arity::fixed(2), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let list = inputs.next_list()?;
+      let cond = inputs.next_list()?;
+      loop {
+        evaluator.eval_list(&list)?;
+        if !evaluator.eval_list_bool(&cond)? {
+          return Ok(None);
+        }
+      }
+    }));
+    add_builtin!("DO.UNTIL", Arity::fixed(2), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let list = inputs.next_list()?;
+      let cond = inputs.next_list()?;
+      loop {
+        evaluator.eval_list(&list)?;
+        if evaluator.eval_list_bool(&cond)? {
+          return Ok(None);
+        }
+      }
+    }));
+    add_builtin!(FOREVER, Arity::fixed(1), (|evaluator, inputs| {
+      let list = inputs.next_list()?;
+      let mut count = 1;
+      loop {
+        evaluator.eval_counted_list(&list, count)?;
+        count += 1;
+      }
+    }));
+
+    add_builtin!(REPEAT, Arity::fixed(2), (|evaluator, inputs| {
+      let repeat = inputs.next_number()?;
+      let list = inputs.next_list()?;
+      for count in 1 ..= repeat as i32 {
+        evaluator.eval_counted_list(&list, count)?;
+      }
+      Ok(None)
+    }));
+    add_builtin!(REPCOUNT, "#", Arity::fixed(0), (|evaluator: &mut Evaluator, _inputs: &mut Inputs| {
+      Ok(Some(Value::Num(*evaluator.stack_repcount.last().unwrap_or(&-1) as NumType)))
+    }));
+    add_builtin!(FOR, Arity::fixed(4), (|evaluator, inputs| {
+      // TODO: FOR with variable step.
+      let var = inputs.next_word()?;
+      let start = inputs.next_number()?;
+      let end = inputs.next_number()?;
+      let list = inputs.next_list()?;
+      let step = 1.0;
+      let mut i = start;
+      while i <= end {
+        evaluator.set(var.clone(), Value::Num(i));
+        evaluator.eval_list(&list)?;
+        i += step;
+      }
+      Ok(None)
+    }));
+
+    add_builtin!(SETXY, Arity::fixed(1), (|evaluator: &mut Evaluator, inputs: &mut Inputs| {
+      let mut point = Inputs::new("SETXY", inputs.next_list()?.iter().cloned().collect());
+      let x = point.next_number()?;
+      let y = point.next_number()?;
+      evaluator.turtle.setxy(x, y);
+      Ok(None)
+    }));
+    add_builtin!(GETXY, Arity::fixed(0), (|evaluator: &mut Evaluator, _inputs: &mut Inputs| {
+      let (x, y) = evaluator.turtle.getxy();
+      Ok(Some(Value::List(List::from(vec![Value::Num(x), Value::Num(y)]))))
+    }));
+    add_direct_builtin_num!(SETH, SETHEADING; setheading);
+    add_direct_builtin_num!(SETX; setx);
+    add_direct_builtin_num!(SETY; sety);
+    add_direct_builtin_ret_num!(HEADING; heading);
+    add_direct_builtin_ret_num!(XCOR; xcor);
+    add_direct_builtin_ret_num!(YCOR; ycor);
+    add_direct_builtin_num!(FD, FORWARD; fd);
+    add_direct_builtin_num!(BK, BACK; bk);
+    add_direct_builtin_num!(RT, RIGHT; rt);
+    add_direct_builtin_num!(LT, LEFT; lt);
+    add_direct_builtin!(CS, CLEARSCREEN; clearscreen);
+    add_direct_builtin!(CLEAN; clean);
+    add_direct_builtin!(HOME; home);
+    add_direct_builtin!(PD, PENDOWN; pendown);
+    add_direct_builtin!(PU, PENUP; penup);
+  }
+
+  // xorshift64*, good enough for RANDOM and doesn't need a dependency.
+  fn next_random(&mut self) -> u64 {
+    self.random_state ^= self.random_state >> 12;
+    self.random_state ^= self.random_state << 25;
+    self.random_state ^= self.random_state >> 27;
+    self.random_state.wrapping_mul(0x2545F4914F6CDD1D)
+  }
+
+  // Everything in the workspace: procedures, variables and property lists.
+  fn workspace_source(&self) -> Vec<String> {
+    let mut lines = Vec::new();
+    for name in self.procedure_names() {
+      lines.extend(self.procedure_source(&name));
+    }
+    lines.extend(self.names_source());
+    lines.extend(self.plist_source());
+    lines
+  }
+
+  // Feeds the lines of the file as if they were typed in.
+  fn load(&mut self, file_name: &str) -> Result<(), Unwind> {
+    let file = match std::fs::File::open(file_name) {
+      Ok(file) => {file},
+      Err(err) => {
+        return Err(ErrorKind::CantOpen(file_name.to_string(), err.to_string()).into());
+      }
+    };
+    for line in std::io::BufReader::new(file).lines() {
+      match line {
+        Ok(line) => {
+          self.feed(&line);
+        },
+        Err(err) => {
+          return Err(ErrorKind::CantRead(file_name.to_string(), err.to_string()).into());
+        }
+      }
+    }
+    Ok(())
+  }
+
+  fn save(&self, file_name: &str, lines: &[String]) -> Result<(), Unwind> {
+    let text: String = lines.iter().map(|line| format!("{}\n", line)).collect();
+    if let Err(err) = std::fs::write(file_name, text) {
+      return Err(ErrorKind::CantWrite(file_name.to_string(), err.to_string()).into());
+    }
+    Ok(())
+  }
+
+  fn procedure_names(&self) -> Vec<WordType> {
+    let mut names: Vec<WordType> = self.procedure_slots.keys().filter(|name| {
+      self.user_function(name).is_some()
+    }).cloned().collect();
+    names.sort();
+    names
+  }
+
+  fn procedure_title(&self, name: &str) -> String {
+    self.user_function(name).unwrap().title.clone()
+  }
+
+  // The procedure as it was typed in, with the comments and the indentation.
+  fn procedure_source(&self, name: &str) -> Vec<String> {
+    let mut lines = vec![self.procedure_title(name)];
+    lines.extend(self.user_function(name).unwrap().source.iter().cloned());
+    lines.push("END".to_string());
+    lines
+  }
+
+  // The text PO prints, the errors are the names which aren't in the workspace.
+  fn contents_source(&self, name: &str, contents: &Contents) -> Result<Vec<String>, Unwind> {
+    let mut lines = Vec::new();
+    for procedure in contents.procedures.iter() {
+      self.check_procedure(name, procedure)?;
+      lines.extend(self.procedure_source(procedure));
+    }
+    for var in contents.vars.iter() {
+      let value = self.vars.get(var).cloned().flatten();
+      let value = value.ok_or_else(|| Unwind::from(ErrorKind::NotAName(var.clone())))?;
+      lines.push(format!("MAKE \"{} {}", var, quoted(&value)));
+    }
+    for plist in contents.plists.iter() {
+      for (prop, value) in self.plists.get(plist).into_iter().flatten() {
+        lines.push(format!("PPROP \"{} \"{} {}", plist, prop, quoted(value)));
+      }
+    }
+    Ok(lines)
+  }
+
+  // Only the user defined procedures can be printed and erased.
+  fn check_procedure(&self, name: &str, procedure: &str) -> Result<(), Unwind> {
+    if self.builtin_functions.contains_key(procedure) {
+      return Err(doesnt_like(name, &Value::Word(procedure.to_string())));
+    }
+    if self.user_function(procedure).is_none() {
+      return Err(ErrorKind::DontKnowHow(procedure.to_string()).into());
+    }
+    Ok(())
+  }
+
+  // Erases nothing when one of the procedures doesn't exist.
+  fn erase(&mut self, name: &str, contents: &Contents) -> Result<Option<Value>, Unwind> {
+    for procedure in contents.procedures.iter() {
+      self.check_procedure(name, procedure)?;
+    }
+    for procedure in contents.procedures.iter() {
+      self.user_functions[self.procedure_slots[procedure]] = None;
+      self.parser.remove_arity(procedure);
+    }
+    if !contents.procedures.is_empty() {
+      self.forget_parsed();
+    }
+    for var in contents.vars.iter() {
+      self.vars.remove(var);
+    }
+    for plist in contents.plists.iter() {
+      self.plists.remove(plist);
+    }
+    Ok(None)
+  }
+
+  // Defining or erasing a procedure can change how the bodies parse, they get parsed again.
+  fn forget_parsed(&mut self) {
+    for function in self.user_functions.iter_mut().flatten() {
+      function.lines = None;
+      function.code = None;
+    }
+  }
+
+  // The variables of the running procedure as the LOCAL and LOCALMAKE instructions that make
+  // them, sorted by name.
+  fn locals_source(&self) -> Vec<String> {
+    let local_vars = self.stack_vars.last().unwrap();
+    let mut vars: Vec<&WordType> = local_vars.keys().collect();
+    vars.sort();
+    vars.iter().map(|var| {
+      match local_vars[*var] {
+        Some(ref value) => { format!("LOCALMAKE \"{} {}", var, quoted(value)) },
+        None => { format!("LOCAL \"{}", var) },
+      }
+    }).collect()
+  }
+
+  // The global variables as MAKE instructions.
+  fn names_source(&self) -> Vec<String> {
+    self.global_names().iter().filter_map(|var| {
+      self.vars[var].as_ref().map(|value| format!("MAKE \"{} {}", var, quoted(value)))
+    }).collect()
+  }
+
+  fn global_names(&self) -> Vec<WordType> {
+    let mut vars: Vec<WordType> = self.vars.keys().cloned().collect();
+    vars.sort();
+    vars
+  }
+
+  // ERN takes the variable away from the innermost procedure that has it, or from the globals.
+  fn erase_var(&mut self, var: &str) {
+    match self.stack_vars.iter_mut().rev().find(|local_vars| local_vars.contains_key(var)) {
+      Some(local_vars) => { local_vars.remove(var); },
+      None => { self.vars.remove(var); },
+    }
+  }
+
+  // PPROP instructions which put the properties back, sorted by the name of the property list.
+  fn plist_source(&self) -> Vec<String> {
+    let mut names: Vec<&WordType> = self.plists.keys().collect();
+    names.sort();
+    names.iter().flat_map(|name| {
+      self.plists[*name].iter().map(move |(prop, value)| {
+        format!("PPROP \"{} \"{} {}", name, prop, quoted(value))
+      })
+    }).collect()
+  }
+
+  // Runs the list as instructions, REPEAT 4 [4] complains about what to do with 4.  RUN [4] uses
+  // eval_list_value and outputs 4.
+  fn eval_list(&mut self, list: &List) -> Result<(), Unwind> {
+    match self.eval_list_value(list)? {
+      None => { Ok(()) },
+      Some(other) => { Err(unused_output(other)) },
+    }
+  }
+
+  // Runs the list as one pass of a REPEAT or FOREVER, REPCOUNT outputs the count.
+  fn eval_counted_list(&mut self, list: &List, count: i32) -> Result<(), Unwind> {
+    self.with_stacks(|evaluator| {
+      evaluator.stack_repcount.push(count);
+      evaluator.eval_list(list)
+    })
+  }
+
+  // Same as eval_list, but the last expression in the list is allowed to output a value, which
+  // is then the output of the whole list (IF :X > 0 [1] [2]).  Running a list is a level deeper,
+  // a procedure recursing through IF, REPEAT, RUN, MAP, ... goes deeper with every one of them.
+  fn eval_list_value(&mut self, list: &List) -> Result<Option<Value>, Unwind> {
+    self.deeper(|evaluator| {
+      match evaluator.mode {
+        Mode::Compile => { evaluator.run_list(list) },
+        Mode::Interpret => { evaluator.walk_list(list) },
+      }
+    })
+  }
+
+  fn run_list(&mut self, list: &List) -> Result<Option<Value>, Unwind> {
+    let code = self.compile_list(list)?;
+    self.run_code(&code, 0)
+  }
+
+  fn walk_list(&mut self, list: &List) -> Result<Option<Value>, Unwind> {
+    let exprs = self.parser.parse_list(list)?;
+    let mut ret = None;
+    for (i, expr) in exprs.iter().enumerate() {
+      ret = self.eval(expr)?;
+      if i + 1 < exprs.len() {
+        if let Some(value) = ret {
+          return Err(unused_output(value));
+        }
+      }
+    }
+    Ok(ret)
+  }
+
+  // The compiled code is kept with the list, next to the parsed one.
+  fn compile_list(&mut self, list: &List) -> Result<Rc<Code>, Unwind> {
+    let exprs = self.parser.parse_list(list)?;
+    if let Some(code) = list.compiled(&exprs) {
+      return Ok(code);
+    }
+    let code = Rc::new(compiler::compile_list(&exprs, &self.parser, &|name| self.callee(name)));
+    list.set_compiled(code.clone());
+    Ok(code)
+  }
+
+  // APPLY, INVOKE, MAP, ... call the procedure named by a word, or run a template with the inputs:
+  // [? * ?2] gets them in the slots, [[A B] :A * :B] as the variables A and B.  The name is the
+  // one in the errors about the number of inputs.
+  fn apply(&mut self, name: &str, template: &Value, inputs: Vec<Value>)
+      -> Result<Option<Value>, Unwind> {
+    match template {
+      Value::Word(procedure) => {
+        // Calls from the code are checked by the parser, these aren't.
+        if let Some(builtin) = self.builtin(procedure) {
+          check_inputs(procedure, &builtin.arity, inputs.len())?;
+        }
+        self.call(procedure, inputs)
+      },
+      Value::List(list) => {
+        match list.first() {
+          Some(Value::List(names)) => {
+            let arity = Arity::fixed(names.len());
+            check_inputs(name, &arity, inputs.len())?;
+            let mut vars = VarsType::new();
+            for (var, input) in names.iter().zip(inputs) {
+              match var {
+                Value::Word(var) => { vars.insert(var.clone(), Some(input)); },
+                other => { return Err(doesnt_like(name, other)); },
+              }
+            }
+            let body = list.butfirst().unwrap();
+            self.with_stacks(|evaluator| {
+              evaluator.stack_vars.push(vars);
+              evaluator.eval_list_value(&body)
+            })
+          },
+          _ => {
+            self.with_stacks(|evaluator| {
+              evaluator.template_inputs.push(inputs);
+              evaluator.eval_list_value(list)
+            })
+          },
+        }
+      },
+      other => { Err(doesnt_like(name, other)) },
+    }
+  }
+
+  // One pass of MAP, FOREACH, ... with the template, # outputs the count the same as REPCOUNT.
+  fn apply_counted(&mut self, name: &str, template: &Value, inputs: Vec<Value>, count: usize)
+      -> Result<Option<Value>, Unwind> {
+    self.with_stacks(|evaluator| {
+      evaluator.stack_repcount.push(count as i32);
+      evaluator.apply(name, template, inputs)
+    })
+  }
+
+  // The templates of MAP, REDUCE, ... have to output something.
+  fn apply_output(&mut self, name: &str, template: &Value, inputs: Vec<Value>, count: usize)
+      -> Result<Value, Unwind> {
+    match self.apply_counted(name, template, inputs, count)? {
+      Some(value) => { Ok(value) },
+      None => { Err(didnt_output(&template.to_string(), name)) },
+    }
+  }
+
+  // The ones of FILTER, FIND, ... output TRUE or FALSE.
+  fn apply_bool(&mut self, name: &str, template: &Value, inputs: Vec<Value>, count: usize)
+      -> Result<bool, Unwind> {
+    let value = self.apply_output(name, template, inputs, count)?;
+    as_bool(&value).ok_or_else(|| doesnt_like(name, &value))
+  }
+
+  // The input in slot index (from 1) of the innermost explicit-slot template, ? or ?2 is the name.
+  fn slot(&self, name: &str, index: usize) -> Result<Option<Value>, Unwind> {
+    let input = self.template_inputs.last().and_then(|inputs| inputs.get(index.wrapping_sub(1)));
+    match input {
+      Some(input) => { Ok(Some(input.clone())) },
+      None => { Err(ErrorKind::NoValue(name.to_string()).into()) },
+    }
+  }
+
+  // The condition of WHILE, UNTIL, ... is a list that gets re-evaluated on every pass.
+  fn eval_list_bool(&mut self, list: &List) -> Result<bool, Unwind> {
+    match self.eval_list_value(list)? {
+      Some(value) => { as_bool(&value).ok_or_else(|| doesnt_like("the condition", &value)) },
+      None => { Err(ErrorKind::NotCondition(Value::List(list.clone())).into()) },
+    }
+  }
+
+  // TO FOO :A :B starts the definition, the following lines get collected by feed until END.
+  fn define_user_function(&mut self, args: &ListType) -> Result<(), Unwind> {
+    let mut args = args.iter();
+    match args.next() {
+      Some(AST::Function(name)) => {
+        if self.builtin_functions.contains_key(name) {
+          return Err(ErrorKind::IsPrimitive(name.clone()).into());
+        }
+        let mut inputs = ArgsType::new();
+        for arg in args {
+          if let AST::Var(arg) = arg {
+            inputs.push(arg.clone());
+          } else {
+            return Err(doesnt_like("TO", &to_input(arg)));
+          }
+        }
+        // Replaced by the line as it was typed in, when there's one (feed).
+        let args: String = inputs.iter().map(|arg| format!(" :{}", arg)).collect();
+        let title = format!("TO {}{}", name, args);
+        let definition = Definition { name: name.clone(), args: inputs, title, lines: Vec::new() };
+        self.definition = Some(definition);
+      },
+      Some(arg) => {
+        return Err(doesnt_like("TO", &to_input(arg)));
+      },
+      None => {
+        return Err(ErrorKind::NotEnoughInputs("TO".to_string()).into());
+      }
+    }
+    Ok(())
+  }
+
+  fn add_user_function_line(&mut self, input: &str) -> Result<(), Box<LogoError>> {
+    let definition = self.definition.as_mut().unwrap();
+    match first_word(input).as_deref() {
+      Some("TO") => {
+        return Err(ErrorKind::ToInDefinition(definition.name.clone()).into());
+      },
+      Some("END") => {
+        // End of function definition, save it.
+        let Definition { name, args, title, lines: source } = self.definition.take().unwrap();
+        self.parser.set_arity(&name, Arity::fixed(args.len()));
+        self.forget_parsed();
+        let function = UserFunction { args: Rc::new(args), title, source, lines: None, code: None };
+        let slot = match self.procedure_slots.get(&name) {
+          Some(&slot) => { slot },
+          None => {
+            self.user_functions.push(None);
+            self.procedure_slots.insert(name.clone(), self.user_functions.len() - 1);
+            self.user_functions.len() - 1
+          },
+        };
+        self.user_functions[slot] = Some(function);
+        if self.mode == Mode::Compile {
+          // Calls to procedures which aren't defined yet don't parse, those get compiled on their
+          // first call instead (and the error shows up there).
+          let _ = self.prepare_user_function(&name, slot);
+        }
+      },
+      _ => {
+        // Collect the line.
+        definition.lines.push(input.trim_end_matches('\n').to_string());
+      },
+    }
+    Ok(())
+  }
+
+  // Value of an input to a procedure or an operator, it has to output something.
+  fn eval_input(&mut self, arg: &AST, to: &str) -> Result<Value, Unwind> {
+    match self.eval(arg)? {
+      Some(input) => { Ok(input) },
+      None => { Err(didnt_output(&callee_name(arg), to)) },
+    }
+  }
+
+  fn eval_inputs(&mut self, name: &str, args: &ListType) -> Result<Vec<Value>, Unwind> {
+    let mut inputs = Vec::new();
+    for arg in args {
+      inputs.push(self.eval_input(arg, name)?);
+    }
+    Ok(inputs)
+  }
+
+  // Calls a builtin or user defined procedure with already evaluated inputs.
+  fn call(&mut self, name: &str, inputs: Vec<Value>) -> Result<Option<Value>, Unwind> {
+    match self.callee(name) {
+      Some(callee) => { self.call_callee(name, callee, inputs) },
+      None => { Err(ErrorKind::DontKnowHow(name.to_string()).into()) },
+    }
+  }
+
+  fn call_callee(&mut self, name: &str, callee: Callee, inputs: Vec<Value>)
+      -> Result<Option<Value>, Unwind> {
+    match callee {
+      Callee::Builtin(index) => {
+        let function = self.builtins[index].function.clone();
+        function(self, &mut Inputs::new(name, inputs))
+      },
+      Callee::User(slot) if self.user_functions[slot].is_some() => {
+        self.call_user_function(name, slot, inputs)
+      },
+      Callee::User(_) => { Err(ErrorKind::DontKnowHow(name.to_string()).into()) },
+    }
+  }
+
+  // Parses the procedure if it isn't yet, and compiles it too when running compiled code.
+  fn prepare_user_function(&mut self, name: &str, slot: usize) -> Result<(), Unwind> {
+    let function = self.user_functions[slot].as_ref().unwrap();
+    let lines = match function.lines {
+      Some(ref lines) => { lines.clone() },
+      None => {
+        let lines = Rc::new(self.parser.parse_lines(&function.source)?);
+        self.user_functions[slot].as_mut().unwrap().lines = Some(lines.clone());
+        lines
+      },
+    };
+    if self.mode == Mode::Compile && self.user_functions[slot].as_ref().unwrap().code.is_none() {
+      let code = compiler::compile_procedure(name, &lines, &self.parser, &|name| self.callee(name));
+      self.user_functions[slot].as_mut().unwrap().code = Some(Rc::new(code));
+    }
+    Ok(())
+  }
+
+  fn call_user_function(&mut self, name: &str, slot: usize, inputs: Vec<Value>)
+      -> Result<Option<Value>, Unwind> {
+    // Tail calls run here one after the other instead of nesting, so tail recursion doesn't grow
+    // the Rust stack.  The variables of the finished procedures stay, the called ones can see them.
+    let mut tails: Vec<Tail> = Vec::new();
+    let mut ret = self.deeper(|evaluator| evaluator.run_tail_calls(name, slot, inputs, &mut tails));
+    for tail in tails.iter().rev() {
+      ret = match (tail, ret?) {
+        (Tail::Instruction(caller, line), Some(value)) => {
+          Err(self.locate(unused_output(value), Some(caller), Some(*line)))
+        },
+        (Tail::Instruction(..), None) => { Ok(None) },
+        (Tail::Output(caller, line, callee, output), None) => {
+          Err(self.locate(didnt_output(callee, output), Some(caller), Some(*line)))
+        },
+        (Tail::Output(..), Some(value)) => { Ok(Some(value)) },
+      };
+    }
+    ret
+  }
+
+  // Runs the procedure and the ones it tail calls, keeping the last two checks of their outputs
+  // in tails.
+  fn run_tail_calls(&mut self, name: &str, slot: usize, inputs: Vec<Value>, tails: &mut Vec<Tail>)
+      -> Result<Option<Value>, Unwind> {
+    let base = self.stack_vars.len();
+    let mut ret = self.run_user_function(name, slot, inputs, base);
+    loop {
+      match ret {
+        Err(Unwind::TailCall(tail_call)) => {
+          let (next, next_slot, next_inputs, tail) = *tail_call;
+          // The output of a call that passed a check always fails a check of the other kind, so
+          // only the last two make a difference.
+          if tails.last().map(std::mem::discriminant) == Some(std::mem::discriminant(&tail)) {
+            tails.pop();
+          }
+          tails.push(tail);
+          if tails.len() > 2 {
+            tails.remove(0);
+          }
+          ret = self.run_user_function(&next, next_slot, next_inputs, base);
+        },
+        ret => { return ret; },
+      }
+    }
+  }
+
+  // Runs the procedure with its inputs as local variables.  A chain of tail calls shares one frame,
+  // the inputs of each procedure go into the one of the procedure before it, which is done and
+  // can't see the difference.  Tail recursion (SPIRAL :N + 2) stays at one frame, with LOCALs too.
+  fn run_user_function(&mut self, name: &str, slot: usize, inputs: Vec<Value>, base: usize)
+      -> Result<Option<Value>, Unwind> {
+    // The body doesn't parse.
+    if let Err(err) = self.prepare_user_function(name, slot) {
+      return Err(self.locate(err, Some(name), None));
+    }
+    let function = self.user_functions[slot].as_ref().unwrap();
+    let params = function.args.clone();
+    let lines = function.lines.clone().unwrap();
+    let code = if self.mode == Mode::Compile { function.code.clone() } else { None };
+    if inputs.len() != params.len() {
+      let name = name.to_string();
+      let kind = if inputs.len() < params.len() {
+        ErrorKind::NotEnoughInputs(name)
+      } else {
+        ErrorKind::TooManyInputs(name)
+      };
+      return Err(kind.into());
+    }
+    // Setup the args as local vars.
+    let local_vars = params.iter().cloned().zip(inputs.into_iter().map(Some));
+    if self.stack_vars.len() > base {
+      self.local_vars().extend(local_vars);
+    } else {
+      self.stack_vars.push(local_vars.collect());
+    }
+    self.run_body(name, &lines, code.as_deref())
+  }
+
+  // Runs the lines, STOP, OUTPUT and GO unwind up to here.
+  fn run_body(&mut self, name: &str, lines: &Rc<ListType>, code: Option<&Code>)
+      -> Result<Option<Value>, Unwind> {
+    let mut pos = 0;
+    loop {
+      let ret = match code {
+        Some(code) => { self.run_code(code, code.line_start(pos)) },
+        None => { self.walk_lines(name, lines, pos) },
+      };
+      match ret {
+        Ok(_) | Err(Unwind::Stop) => {
+          return Ok(None);
+        },
+        Err(Unwind::Output(value)) => {
+          return Ok(Some(value));
+        },
+        Err(Unwind::Go(label)) => {
+          match Self::find_label(lines, &label) {
+            Some(label_pos) => {
+              pos = label_pos;
+            },
+            None => {
+              return Err(self.locate(ErrorKind::CantFindLabel(label).into(), Some(name), None));
+            },
+          }
+        },
+        Err(err) => {
+          return Err(err);
+        },
+      }
+    }
+  }
+
+  // The tree-walking way of running the lines of a procedure, starting from the given one.
+  fn walk_lines(&mut self, name: &str, lines: &ListType, pos: usize)
+      -> Result<Option<Value>, Unwind> {
+    for i in pos .. lines.len() {
+      if let Err(err) = self.walk_line(name, lines, i) {
+        return Err(self.locate(err, Some(name), Some(i)));
+      }
+    }
+    Ok(None)
+  }
+
+  fn walk_line(&mut self, name: &str, lines: &ListType, i: usize) -> Result<(), Unwind> {
+    let exprs = compiler::line_exprs(&lines[i]);
+    for (j, expr) in exprs.iter().enumerate() {
+      let last = i + 1 == lines.len() && j + 1 == exprs.len();
+      if let Some((callee, args, output)) = compiler::tail_call(expr, last) {
+        if let Some(Callee::User(slot)) = self.callee(callee).filter(|_| self.user_function(callee).is_some()) {
+          let inputs = self.eval_inputs(callee, args)?;
+          let tail = match output {
+            Some(output) => { Tail::Output(name.to_string(), i, callee.clone(), output.clone()) },
+            None => { Tail::Instruction(name.to_string(), i) },
+          };
+          return Err(Unwind::TailCall(Box::new((callee.clone(), slot, inputs, tail))));
+        }
+      }
+      if let Some(other) = self.eval(expr)? {
+        return Err(unused_output(other));
+      }
+    }
+    Ok(())
+  }
+
+  // The stack machine which runs the compiled code, it has to do the same as eval does with the
+  // parsed code.  Starts at the instruction pc, outputs what it left on the stack at the end.  Like
+  // eval it's on the Rust stack for every level of Logo calls, so it's kept small.
+  fn run_code(&mut self, code: &Code, mut pc: usize) -> Result<Option<Value>, Unwind> {
+    let base = self.stack_values.len();
+    // What the lists compiled in place leave when they get out by an error, STOP, GO, ...
+    let stacks = self.stacks();
+    let mut line = 0;
+    while pc < code.instructions.len() {
+      let instruction = &code.instructions[pc];
+      pc += 1;
+      let ret = match instruction {
+        Instruction::Call(name, callee, count, use_) => {
+          self.run_call(code, line, name, *callee, *count, use_)
+        },
+        Instruction::Eval(ast_node, use_) => {
+          self.eval(ast_node).and_then(|output| {
+            use_output(&callee_name(ast_node), output, use_, &mut self.stack_values)
+          })
+        },
+        Instruction::Unused => { Err(unused_output(self.stack_values.pop().unwrap())) },
+        Instruction::Line(pos) => {
+          line = *pos;
+          Ok(())
+        },
+        Instruction::Jump(target) => {
+          pc = *target;
+          Ok(())
+        },
+        Instruction::JumpUnless(expected, target, name) => {
+          let condition = self.stack_values.pop().unwrap();
+          match as_bool(&condition) {
+            Some(condition) => {
+              if condition != *expected {
+                pc = *target;
+              }
+              Ok(())
+            },
+            None => { Err(doesnt_like(name, &condition)) },
+          }
+        },
+        Instruction::NextPass(target) => {
+          if !self.next_pass() {
+            pc = *target;
+          }
+          Ok(())
+        },
+        operation => { self.run_operation(operation) },
+      };
+      if let Err(err) = ret {
+        self.stack_values.truncate(base);
+        self.restore(stacks);
+        return Err(self.locate(err, code.procedure.as_deref(), Some(line)));
+      }
+    }
+    if self.stack_values.len() > base {
+      Ok(self.stack_values.pop())
+    } else {
+      Ok(None)
+    }
+  }
+
+  fn run_operation(&mut self, instruction: &Instruction) -> Result<(), Unwind> {
+    let stack = &mut self.stack_values;
+    let value = match instruction {
+      Instruction::Push(value) => { value.clone() },
+      Instruction::Var(var) => { self.get(var)? },
+      Instruction::Negate => {
+        let operand = stack.pop().unwrap();
+        negate(operand)?
+      },
+      Instruction::Binary(operator) => {
+        let right = stack.pop().unwrap();
+        let left = stack.pop().unwrap();
+        binary(*operator, left, right)?
+      },
+      Instruction::Comparison(operator) => {
+        let right = stack.pop().unwrap();
+        let left = stack.pop().unwrap();
+        comparison(*operator, left, right)?
+      },
+      Instruction::Nary(operator, count) => {
+        let operands = stack.drain(stack.len() - count ..).collect();
+        nary(*operator, operands)?
+      },
+      Instruction::Enter => {
+        if self.depth >= self.max_depth {
+          return Err(ErrorKind::StackOverflow.into());
+        }
+        self.depth += 1;
+        return Ok(());
+      },
+      Instruction::Leave => {
+        self.depth -= 1;
+        return Ok(());
+      },
+      Instruction::Repeat => {
+        // The number of passes stays on the stack until they're done.
+        let repeat = stack.pop().unwrap();
+        let repeat = to_number(&repeat).ok_or_else(|| doesnt_like("REPEAT", &repeat))?;
+        self.stack_repcount.push(0);
+        Value::Num(repeat as i32 as NumType)
+      },
+      other => {
+        panic!("Not an operation {:?}", other);
+      },
+    };
+    self.stack_values.push(value);
+    Ok(())
+  }
+
+  // Counts the next pass of the REPEAT compiled in place, false when they're all done and gone.
+  fn next_pass(&mut self) -> bool {
+    let repeat = match self.stack_values.last() {
+      Some(Value::Num(repeat)) => { *repeat as i32 },
+      other => { panic!("Not the number of passes {:?}", other); },
+    };
+    let count = self.stack_repcount.last_mut().unwrap();
+    if *count < repeat {
+      *count += 1;
+      true
+    } else {
+      self.stack_repcount.pop();
+      self.stack_values.pop();
+      false
+    }
+  }
+
+  fn run_call(&mut self, code: &Code, line: usize, name: &WordType, callee: Callee, count: usize,
+              use_: &Use) -> Result<(), Unwind> {
+    let inputs: Vec<Value> = self.stack_values.drain(self.stack_values.len() - count ..).collect();
+    if let Callee::User(slot) = callee {
+      let caller = || code.procedure.clone().unwrap_or_default();
+      let tail = match use_ {
+        Use::Tail => { Some(Tail::Instruction(caller(), line)) },
+        Use::TailOutput(output) => { Some(Tail::Output(caller(), line, name.clone(), output.clone())) },
+        _ => { None },
+      };
+      if let Some(tail) = tail {
+        if self.user_functions[slot].is_some() {
+          return Err(Unwind::TailCall(Box::new((name.clone(), slot, inputs, tail))));
+        }
+      }
+    }
+    let output = self.call_callee(name, callee, inputs)?;
+    use_output(name, output, use_, &mut self.stack_values)
+  }
+
+  // Errors get the procedure and the line (None for all of it) they happened in, and on the way
+  // out the calls they came through.  A list that gets run is part of the line running it, so
+  // nothing changes without a procedure.
+  fn locate(&self, err: Unwind, procedure: Option<&str>, line: Option<usize>) -> Unwind {
+    match (err, procedure) {
+      (Unwind::Error(mut err), Some(procedure)) => {
+        let place = self.place(procedure, line);
+        if err.place.is_none() {
+          err.place = Some(place);
+        } else {
+          err.callers.push(place);
+        }
+        Unwind::Error(err)
+      },
+      (other, _) => { other },
+    }
+  }
+
+  // The line is the index of the parsed line, the text comes from the source of the procedure.
+  fn place(&self, procedure: &str, line: Option<usize>) -> Place {
+    let source = self.user_function(procedure).map(|function| &function.source[..]);
+    Place {
+      procedure: procedure.to_string(),
+      line: line.and_then(|line| parser::source_line(source?, line)),
+    }
+  }
+
+  // Labels have to be the first instruction in a line of the procedure (LABEL "LOOP FD 10).
+  fn find_label(lines: &ListType, label: &str) -> Option<usize> {
+    lines.iter().position(|line| {
+      match line {
+        AST::ExprLine(expr_list) => {
+          match expr_list.front() {
+            Some(AST::Call(name, args)) => {
+              name == "LABEL" && args.front() == Some(&AST::Word(label.to_string()))
+            },
+            _ => { false },
+          }
+        },
+        _ => { false },
+      }
+    })
+  }
+
+  // Kept small, it's on the Rust stack for every level of a Logo expression, the arms which need
+  // more room get a function of their own.
+  fn eval(&mut self, ast_node: &AST) -> Result<Option<Value>, Unwind> {
+    match ast_node {
+      AST::Call(name, args) => { self.eval_call(name, args) },
+      AST::ExprLine(expr_list) => { self.eval_line(expr_list) },
+      AST::Parens(expr_list) => {
+        // The parser leaves at most one expression inside, parens with a procedure call first
+        // become just the call.  If the expression list is empty, returns the empty list.
+        match expr_list.front() {
+          Some(expr) => { self.eval(expr) },
+          None => { Ok(Some(Value::List(List::new()))) },
+        }
+      },
+      AST::Var(var_name) => { self.get(var_name).map(Some) },
+      AST::Num(num) => { Ok(Some(Value::Num(*num))) },
+      AST::List(list) => { Ok(Some(Value::List(list.clone()))) },
+      AST::Array(items, origin) => {
+        Ok(Some(Value::Array(Array::new(items.iter().cloned().collect(), *origin))))
+      },
+      AST::Word(string) => { Ok(Some(Value::Word(string.clone()))) },
+      AST::Negation(_) | AST::Comparison(..) | AST::Binary(..) | AST::Nary(..) => {
+        self.eval_operator(ast_node).map(Some)
+      },
+      // The name of a procedure only comes as the input of TO, which doesn't evaluate it.
+      AST::Function(name) => { Err(ErrorKind::DontKnowHow(name.clone()).into()) },
+      // A line which goes on in the next one.
+      AST::None => { Ok(None) },
+    }
+  }
+
+  fn eval_call(&mut self, name: &str, args: &ListType) -> Result<Option<Value>, Unwind> {
+    if name == "TO" {
+      self.define_user_function(args)?;
+      return Ok(None);
+    }
+    let inputs = self.eval_inputs(name, args)?;
+    self.call(name, inputs)
+  }
+
+  // The expressions of a line, only the last one can output something.
+  fn eval_line(&mut self, expr_list: &ListType) -> Result<Option<Value>, Unwind> {
+    let mut ret = None;
+    for (i, expr) in expr_list.iter().enumerate() {
+      ret = self.eval(expr)?;
+      if i + 1 < expr_list.len() {
+        if let Some(value) = ret {
+          return Err(unused_output(value));
+        }
+      }
+    }
+    Ok(ret)
+  }
+
+  fn eval_operator(&mut self, ast_node: &AST) -> Result<Value, Unwind> {
+    match ast_node {
+      AST::Negation(box_operand) => {
+        let operand = self.eval_input(box_operand, "-")?;
+        negate(operand)
+      },
+      AST::Comparison(operator, left_box, right_box) => {
+        let left = self.eval_input(left_box, &operator.to_string())?;
+        let right = self.eval_input(right_box, &operator.to_string())?;
+        comparison(*operator, left, right)
+      },
+      AST::Binary(operator, left_box, right_box) => {
+        let left = self.eval_input(left_box, &operator.to_string())?;
+        let right = self.eval_input(right_box, &operator.to_string())?;
+        binary(*operator, left, right)
+      },
+      AST::Nary(operator, expr_list) => {
+        let mut operands = Vec::new();
+        for operand in expr_list {
+          operands.push(self.eval_input(operand, &operator.to_string())?);
+        }
+        nary(*operator, operands)
+      },
+      other => {
+        panic!("Not an operator {:?}", other);
+      },
+    }
+  }
+
+  pub fn feed(&mut self, input: &str) {
+    if self.definition.is_some() {
+      // Lines of a procedure definition are parsed only when it's called.
+      let result = self.add_user_function_line(input);
+      if let Err(err) = result {
+        self.uncaught(*err);
+      }
+      return;
+    }
+    let ast = match self.parser.parse(input) {
+      Ok(ast) => { ast },
+      Err(err) => {
+        self.uncaught(*err);
+        return;
+      },
+    };
+    // The stacks are back where they were after the line, even when it fails half way.
+    // Nothing at the top level takes what the line outputs.
+    let result = self.with_stacks(|evaluator| {
+      match evaluator.eval(&ast)? {
+        Some(value) => { Err(unused_output(value)) },
+        None => { Ok(()) },
+      }
+    });
+    match result {
+      Ok(()) => {
+        // The TO line typed in on its own is the title the way it was typed, with the comments.
+        if let Some(definition) = self.definition.as_mut() {
+          if first_word(input).as_deref() == Some("TO") {
+            definition.title = input.trim().to_string();
+          }
+        }
+      },
+      Err(err) => {
+        // A TO run by the failed line doesn't go on collecting the lines that follow.
+        self.definition = None;
+        if let Some(err) = top_level_error(err) {
+          self.uncaught(err);
+        }
+      },
+    }
+  }
+}
+
+// How long the lines take to run in each mode, the interpreted one first.
+pub fn time_modes(lines: &[String]) -> (std::time::Duration, std::time::Duration) {
+  let run = |mode| {
+    let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+    evaluator.set_mode(mode);
+    evaluator.random_state = 1;
+    let start = std::time::Instant::now();
+    for line in lines {
+      evaluator.feed(line);
+    }
+    start.elapsed()
+  };
+  (run(Mode::Interpret), run(Mode::Compile))
+}
+
+// Runs the lines in both modes, the compiled one has to draw and print the same as the reference
+// one.  Returns the first difference, lines which only define procedures prove nothing and fail.
+pub fn compare_modes(lines: &[String]) -> Result<(), String> {
+  let run = |mode| {
+    let graphics = turtle::GraphicsStub::new();
+    let mut evaluator = Evaluator::new(Box::new(graphics.clone()));
+    evaluator.set_mode(mode);
+    evaluator.random_state = 1;
+    evaluator.printed = Some(Vec::new());
+    for line in lines {
+      evaluator.feed(line);
+    }
+    let commands = graphics.invocations.replace(Vec::new());
+    (commands, evaluator.printed.take().unwrap())
+  };
+  let (commands, printed) = run(Mode::Interpret);
+  let (compiled_commands, compiled_printed) = run(Mode::Compile);
+  if commands.is_empty() && printed.is_empty() {
+    return Err("Nothing was drawn or printed".to_string());
+  }
+  for i in 0 .. printed.len().max(compiled_printed.len()) {
+    if printed.get(i) != compiled_printed.get(i) {
+      return Err(format!("Printed line {} differs: {:?} compiled {:?}",
+                         i + 1, printed.get(i), compiled_printed.get(i)));
+    }
+  }
+  for i in 0 .. commands.len().max(compiled_commands.len()) {
+    if commands.get(i) != compiled_commands.get(i) {
+      return Err(format!("Turtle command {} differs: {:?} compiled {:?}",
+                         i + 1, commands.get(i), compiled_commands.get(i)));
+    }
+  }
+  Ok(())
+}
+
+#[allow(dead_code)]
+fn main() {
+  // 1 + (2 * (3 + 4 * -5) + -6 * -(-7 + -8)) * 9
+  let graphics = Box::new(turtle::GraphicsStub::new());
+  let mut evaluator = Evaluator::new(graphics);
+  loop {
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).unwrap();
+    // pratt_parse_debug(input.trim());
+    evaluator.feed(&input);
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use CON;
+
+  fn run_test(input: &str, expected: Vec<turtle::Command>) {
+    let lines: Vec<String> = input.lines().map(String::from).collect();
+    assert_eq!(Ok(()), compare_modes(&lines), "{}", input);
+    let graphics_stub = turtle::GraphicsStub::new();
+    let mut evaluator = Evaluator::new(Box::new(graphics_stub.clone()));
+    for line in input.lines() {
+      evaluator.feed(line);
+    }
+    // TODO:
+    // Need to check both the graphics stub and the "stdout" (program output) which needs to be captured better.
+    // Also need to be able to check for errors.
+    let actual = (*graphics_stub.invocations).take();
+    println!("{:?}", actual);
+    assert_eq!(expected, actual);
+  }
+
+  // Feeds all but the last line, and returns the evaluation result of the last one, which has to
+  // be the same in both modes.
+  fn run_result(input: &str) -> Result<Option<Value>, Unwind> {
+    let result = run_result_in(Mode::Compile, input);
+    assert_eq!(run_result_in(Mode::Interpret, input), result, "{}", input);
+    result
+  }
+
+  // The message of the error the last line ends with.
+  fn run_error(input: &str) -> String {
+    match run_result(input) {
+      Err(Unwind::Error(err)) => { err.to_string() },
+      other => { panic!("{} = {:?}", input, other); },
+    }
+  }
+
+  fn run_result_in(mode: Mode, input: &str) -> Result<Option<Value>, Unwind> {
+    let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+    evaluator.set_mode(mode);
+    evaluator.random_state = 1;
+    let mut lines: Vec<&str> = input.lines().collect();
+    let last = lines.pop().unwrap();
+    for line in lines {
+      evaluator.feed(line);
+    }
+    let ast = evaluator.parser.parse(last)?;
+    evaluator.eval(&ast)
+  }
+
+  #[test]
+  fn test_fd() {
+    run_test("FD 50", CON!((0.0, 0.0), (0.0, 50.0)));
   }
 
-  fn current_expr_list(&mut self) -> &mut VecDeque<AST> {
-    self.stack_expr.last_mut().unwrap()
+  #[test]
+  fn test_if() {
+    run_test("IF 1 < 2 [FD 10] IF 1 > 2 [FD 20]", CON!((0.0, 0.0), (0.0, 10.0)));
+    run_test("IFELSE 1 > 2 [FD 10] [FD 20]", CON!((0.0, 0.0), (0.0, 20.0)));
+    run_test("IF 1 > 2 THEN FD 10 ELSE FD 20 BK 5", CON!((0.0, 0.0), (0.0, 20.0), (0.0, 15.0)));
+    run_test("IF \"TRUE THEN [FD 10] ELSE [FD 20]", CON!((0.0, 0.0), (0.0, 10.0)));
+    run_test("FD IFELSE 1 = 1 [10] [20]", CON!((0.0, 0.0), (0.0, 10.0)));
+    // Two lists without parens are an IFELSE, ELSE goes with the nearest IF.
+    run_test("IF 1 > 2 [FD 10] [FD 20]", CON!((0.0, 0.0), (0.0, 20.0)));
+    run_test("IF 1 < 2 THEN IF 1 > 2 THEN FD 10 ELSE FD 20", CON!((0.0, 0.0), (0.0, 20.0)));
+    run_test("IF 1 > 2 THEN IF 1 < 2 THEN FD 10 ELSE FD 20\nFD 5", CON!((0.0, 0.0), (0.0, 5.0)));
+    run_test("IF 1 > 2 THEN IF 1 < 2 THEN FD 10 ELSE FD 20 ELSE FD 30", CON!((0.0, 0.0), (0.0, 30.0)));
   }
 
-  fn set(&mut self, var: WordType, expr: AST) {
-    if self.local_vars().contains_key(&var) {
-      self.local_vars().insert(var, expr);
-    } else {
-      self.vars.insert(var, expr);
-    }
+  #[test]
+  fn test_loops() {
+    let three_lines = CON!((0.0, 0.0), (0.0, 10.0), (0.0, 20.0), (0.0, 30.0));
+    run_test("MAKE \"I 0 WHILE [:I < 3] [FD 10 MAKE \"I :I + 1]", three_lines.clone());
+    run_test("MAKE \"I 0 UNTIL [:I = 3] [FD 10 MAKE \"I :I + 1]", three_lines.clone());
+    run_test("DO.WHILE [FD 10] [YCOR < 30]", three_lines.clone());
+    run_test("DO.UNTIL [FD 10] [YCOR = 30]", three_lines.clone());
+    run_test("WHILE [1 > 2] [FD 10] DO.WHILE [FD 10] [1 > 2]", CON!((0.0, 0.0), (0.0, 10.0)));
+    run_test("TO F\nFOREVER [FD 10 IF YCOR = 30 [STOP]]\nEND\nF", three_lines.clone());
+    run_test("TO F\nFOREVER [FD 10 IF YCOR = 30 [OP 5]]\nEND\nFD F", CON!(
+        (0.0, 0.0), (0.0, 10.0), (0.0, 20.0), (0.0, 30.0), (0.0, 35.0)));
   }
 
-  fn define_builtins(&mut self) {
-    #![allow(unused_parens)]
-    macro_rules! add_builtin {
-      ($name:ident, $closure:tt) => {
-        self.builtin_functions.insert(
-            stringify!($name).to_string(),
-            std::rc::Rc::new($closure));
-      };
-      ($name1:ident, $name2:ident, $closure:tt) => {
-        let rc = std::rc::Rc::new($closure);
-        self.builtin_functions.insert(stringify!($name1).to_string(), rc.clone());
-        self.builtin_functions.insert(stringify!($name2).to_string(), rc.clone());
-      };
-    }
-    macro_rules! add_direct_builtin {
-      ($name:ident, $func:ident) => {
-        add_builtin!($name, (|evaluator: &mut Evaluator| {
-          evaluator.turtle.$func();
-          Ok(AST::None)
-        }));
-      };
-      ($name1:ident, $name2:ident, $func:ident) => {
-        add_builtin!($name1, $name2, (|evaluator: &mut Evaluator| {
-          evaluator.turtle.$func();
-          Ok(AST::None)
-        }));
-      };
-    }
-    macro_rules! add_direct_builtin_num {
-      ($name:ident, $func:ident) => {
-        add_builtin!($name, (|evaluator: &mut Evaluator| {
-          let num = evaluator.get_next_number()?;
-          evaluator.turtle.$func(num);
-          Ok(AST::None)
-        }));
-      };
-      ($name1:ident, $name2:ident, $func:ident) => {
-        add_builtin!($name1, $name2, (|evaluator: &mut Evaluator| {
-          let num = evaluator.get_next_number()?;
-          evaluator.turtle.$func(num);
-          Ok(AST::None)
-        }));
-      };
-    }
-    macro_rules! add_direct_builtin_ret_num {
-      ($name:ident, $func:ident) => {
-        add_builtin!($name, (|evaluator: &mut Evaluator| {
-          let num = evaluator.turtle.$func();
-          Ok(AST::Num(num))
-        }));
-      };
-      ($name1:ident, $name2:ident, $func:ident) => {
-        add_builtin!($name1, $name2, (|evaluator: &mut Evaluator| {
-          let num = evaluator.turtle.$func();
-          Ok(AST::Num(num))
-        }));
-      };
-    }
-    add_builtin!(POPS, (|evaluator| {
-      // TODO: Split this out into something like print_locals / print_globals.
-      for (name, (args, lines)) in evaluator.user_functions.iter() {
-        print!("TO {}", name);
-        for arg in args {
-          print!(" :{}", arg);
-        }
-        println!();
-        for line in lines {
-          println!("{:?}", line);
-        }
-        println!("END");
-      }
-      Ok(AST::None)
-    }));
-    add_builtin!(PONS, (|evaluator| {
-      evaluator.print_locals();
-      evaluator.print_globals();
-      Ok(AST::None)
-    }));
-    add_builtin!(PR, PRINT, (|evaluator: &mut Evaluator| {
-      let object = evaluator.eval_next_expr()?;
-      println!("{:?}", object);
-      Ok(AST::None)
-    }));
-    add_builtin!(OP, OUTPUT, (|evaluator: &mut Evaluator| {
-      Ok(AST::FunctionReturn(Box::new(evaluator.eval_next_expr()?)))
-    }));
-
-    add_builtin!(LOAD, (|evaluator| {
-      let mut file_name = evaluator.get_next_word()?;
-      file_name = file_name.to_lowercase();
-      if !file_name.ends_with(".lgo") {
-        file_name += ".lgo";
-      }
-      let file = match std::fs::File::open(file_name.clone()) {
-        Ok(file) => {file},
-        Err(err) => {
-          return Err(format!("Unable to open file {}: {:?}", file_name, err));
-        }
-      };
-      for line in std::io::BufReader::new(file).lines() {
-        match line {
-          Ok(line) => {
-            evaluator.feed(&line);
-          },
-          Err(err) => {
-            return Err(format!("Error reading line for {}: {:?}", file_name, err));
-          }
-        }
-      }
-      Ok(AST::None)
-    }));
+  #[test]
+  fn test_stop_output() {
+    run_test("TO F\nREPEAT 5 [FOR \"I 1 3 [IF :I = 2 [OP 10 * REPCOUNT]]]\nEND\nFD (F + 5)",
+             CON!((0.0, 0.0), (0.0, 15.0)));
+    run_test("TO F\nREPEAT 5 [FD 10 IF # = 2 [STOP]]\nFD 100\nEND\nF\nFD REPCOUNT + 2",
+             CON!((0.0, 0.0), (0.0, 10.0), (0.0, 20.0), (0.0, 21.0)));
+    run_test("TO F :N\nIF :N = 0 THEN OP 0 ELSE OP :N + F :N - 1\nEND\nFD F 4",
+             CON!((0.0, 0.0), (0.0, 10.0)));
+  }
 
-    add_builtin!(MAKE, (|evaluator| {
-      let var = evaluator.get_next_word()?;
-      let expr = evaluator.eval_next_expr()?;
-      evaluator.set(var, expr);
-      Ok(AST::None)
-    }));
-    add_builtin!(LPUT, (|evaluator| {
-      // TODO: Support also words here.
-      // LPUT word1/list1 word2/list2
-      // list1 -> !word2 (list2)
-      // word1 + word2 -> word
-      let list1 = AST::List(evaluator.get_next_list()?);
-      let mut list2 = AST::List(evaluator.get_next_list()?);
-      if let AST::List(ref mut list) = list2 {
-        list.push_back(list1);
-      }
-      Ok(list2)
-    }));
-    add_builtin!(ITEM, (|evaluator| {
-      // TODO: Implement word and num also.
-      // ITEM num list/word/num
-      let num = evaluator.get_next_number()? as usize;
-      let list = evaluator.get_next_list()?;
-      if num < 1 || num > list.len() {
-        Err(format!("ITEM needs a number between 1 and {} as its first input.", list.len()))
-      } else {
-        Ok(list[num - 1].clone())
-      }
-    }));
-    add_builtin!(LIST, (|evaluator| {
-      // TODO: LIST word/list1 word/list2 or (LIST ...)
-      // TODO: Does this need some type checking?  Don't think so - the only fully evaluated types
-      // are nums, lists, and words.
-      let item1 = evaluator.eval_next_expr()?;
-      let item2 = evaluator.eval_next_expr()?;
-      let mut list = ListType::new();
-      list.push_back(item1);
-      list.push_back(item2);
-      Ok(AST::List(list))
-    }));
+  #[test]
+  fn test_label_go() {
+    run_test("TO F\nMAKE \"N 0\nLABEL \"AGAIN\nFD 10\nMAKE \"N :N + 1\nIF :N < 3 THEN GO \"AGAIN\nEND\nF",
+             CON!((0.0, 0.0), (0.0, 10.0), (0.0, 20.0), (0.0, 30.0)));
+    assert_eq!("Can't find label NOWHERE in F",
+               run_error("TO F\nGO \"NOWHERE\nEND\nF"));
+    assert_eq!(Err(Unwind::Go("NOWHERE".to_string())), run_result("GO \"NOWHERE"));
+  }
 
-    add_builtin!(REPEAT, (|evaluator| {
-      let repeat = evaluator.get_next_number()?;
-      let list = evaluator.get_next_list()?;
-      for _ in 0 .. repeat as i32 {
-        evaluator.eval_list(&list)?;
-      }
-      Ok(AST::None)
-    }));
-    add_builtin!(FOR, (|evaluator| {
-      // TODO: FOR with variable step.
-      let var = evaluator.get_next_word()?;
-      let start = evaluator.get_next_number()?;
-      let end = evaluator.get_next_number()?;
-      let list = evaluator.get_next_list()?;
-      let step = 1.0;
-      let mut i = start;
-      while i <= end {
-        evaluator.set(var.clone(), AST::Num(i));
-        evaluator.eval_list(&list)?;
-        i += step;
-      }
-      Ok(AST::None)
-    }));
+  #[test]
+  fn test_dynamic_scope() {
+    let procedures = "TO G\nMAKE \"A :A + 1\nEND\nTO F :A\nG\nFD :A\nEND\n";
+    run_test(&format!("{}F 10", procedures), CON!((0.0, 0.0), (0.0, 11.0)));
+    assert_eq!(":A is not a Logo name.",
+               run_error(&format!("{}F 10\n:A", procedures)));
+  }
 
-    add_builtin!(SETXY, (|evaluator: &mut Evaluator| {
-      let list = evaluator.get_next_list()?;
-      evaluator.stack_expr.push(list);
-      let x = evaluator.get_next_number()?;
-      let y = evaluator.get_next_number()?;
-      evaluator.turtle.setxy(x, y);
-      evaluator.stack_expr.pop();
-      Ok(AST::None)
-    }));
-    add_builtin!(GETXY, (|evaluator: &mut Evaluator| {
-      let (x, y) = evaluator.turtle.getxy();
-      let mut list = ListType::new();
-      list.push_back(AST::Num(x));
-      list.push_back(AST::Num(y));
-      Ok(AST::List(list))
-    }));
-    add_direct_builtin_num!(SETH, SETHEADING, setheading);
-    add_direct_builtin_num!(SETX, setx);
-    add_direct_builtin_num!(SETY, sety);
-    add_direct_builtin_ret_num!(HEADING, heading);
-    add_direct_builtin_ret_num!(XCOR, xcor);
-    add_direct_builtin_ret_num!(YCOR, ycor);
-    add_direct_builtin_num!(FD, FORWARD, fd);
-    add_direct_builtin_num!(BK, BACK, bk);
-    add_direct_builtin_num!(RT, RIGHT, rt);
-    add_direct_builtin_num!(LT, LEFT, lt);
-    add_direct_builtin!(CS, CLEARSCREEN, clearscreen);
-    add_direct_builtin!(CLEAN, clean);
-    add_direct_builtin!(HOME, home);
-    add_direct_builtin!(PD, PENDOWN, pendown);
-    add_direct_builtin!(PU, PENUP, penup);
+  #[test]
+  fn test_local() {
+    run_test("MAKE \"X 5\nTO F\n(LOCAL \"X \"Y)\nMAKE \"X 20\nMAKE \"Y 1\nFD :X + :Y\nEND\nF\nFD :X",
+             CON!((0.0, 0.0), (0.0, 21.0), (0.0, 26.0)));
+    run_test("TO F\nLOCALMAKE \"Z 3\nFD :Z\nEND\nF", CON!((0.0, 0.0), (0.0, 3.0)));
+    assert_eq!("Z has no value in F: OP :Z",
+               run_error("TO F\nLOCAL [Z]\nOP :Z\nEND\nF"));
   }
 
-  fn print_locals(&mut self) {
-    println!("Locals:");
-    for (var, expr) in self.local_vars().iter() {
-      println!("{} is {:?}", var, expr);
-    }
+  fn word(word: &str) -> Value {
+    Value::Word(word.to_string())
   }
 
-  fn print_globals(&mut self) {
-    println!("Globals:");
-    for (var, expr) in self.vars.iter() {
-      println!("{} is {:?}", var, expr);
-    }
+  fn list(items: &[Value]) -> Value {
+    Value::List(List::from(items.to_vec()))
   }
 
-  // TODO: eval_next_as_number, eval_next, as_number ?
-  fn get_number(&mut self, ast_node: &AST) -> Result<NumType, String> {
-    match self.eval(ast_node)? {
-      AST::Num(num) => { Ok(num) },
-      _ => { Err(format!("Expr doesn't evaluate to a number {:?}", ast_node)) }
+  #[test]
+  fn test_word_list_primitives() {
+    for (input, expected) in &[
+      ("FIRST \"HELLO", word("H")),
+      ("FIRST [A B]", word("A")),
+      ("COUNT 12345", Value::Num(5.0)),
+      ("COUNT [1 [2 3]]", Value::Num(2.0)),
+      ("BF [1 2 3]", list(&[Value::Num(2.0), Value::Num(3.0)])),
+      ("BUTFIRST 123", word("23")),
+      ("BF 100", word("00")),
+      ("BF \"A.5", word(".5")),
+      ("BL 100", word("10")),
+      ("WORD 1 0", word("10")),
+      ("LPUT 0 \"1", word("10")),
+      ("(BF 123) > 5", word("TRUE")),
+      ("EQUAL? BF 100 0", word("TRUE")),
+      ("BL \"HELLO", word("HELL")),
+      ("LAST [1 [2 3]]", list(&[Value::Num(2.0), Value::Num(3.0)])),
+      ("FPUT 1 [2]", list(&[Value::Num(1.0), Value::Num(2.0)])),
+      ("LPUT \"C \"AB", word("ABC")),
+      ("SE [1 2] 3", list(&[Value::Num(1.0), Value::Num(2.0), Value::Num(3.0)])),
+      ("WORD \"A 1", word("A1")),
+      ("ITEM 2 \"ABC", word("B")),
+      ("ITEM 2 [A [B]]", list(&[word("B")])),
+      ("EMPTY? []", word("TRUE")),
+      ("EMPTY? \"A", word("FALSE")),
+      ("LIST? [1]", word("TRUE")),
+      ("WORD? 1", word("TRUE")),
+      ("NUMBER? \"12", word("TRUE")),
+      ("NUMBER? \"A12", word("FALSE")),
+      ("MEMBER? 2 [1 2 3]", word("TRUE")),
+      ("MEMBER? \"LL \"HELLO", word("TRUE")),
+      ("EQUAL? [1 [2]] [1 [2]]", word("TRUE")),
+      ("EQUAL? 5 \"5", word("TRUE")),
+      ("FD \"5 YCOR", Value::Num(5.0)),
+    ] {
+      assert_eq!(Ok(Some(expected.clone())), run_result(input), "{}", input);
     }
+    assert!(run_result("FIRST []").is_err());
+    assert!(run_result("ITEM 4 [1 2 3]").is_err());
   }
 
-  fn get_list(&mut self, ast_node: &AST) -> Result<ListType, String> {
-    match self.eval(ast_node)? {
-      AST::List(list) => { Ok(list) },
-      _ => { Err(format!("Expr doesn't evaluate to a list {:?}", ast_node)) }
+  #[test]
+  fn test_math() {
+    for (input, expected) in &[
+      ("SQRT 16", 4.0),
+      ("POWER 2 10", 1024.0),
+      ("EXP 0", 1.0),
+      ("LN 1", 0.0),
+      ("LOG10 1000", 3.0),
+      ("SIN 90", 1.0),
+      ("COS 180", -1.0),
+      ("TAN 45", 1.0),
+      ("ARCTAN 1", 45.0),
+      ("INT 3.7", 3.0),
+      ("INT -3.7", -3.0),
+      ("ROUND 2.5", 3.0),
+      ("ABS -2", 2.0),
+      ("REMAINDER -7 3", -1.0),
+      ("MODULO -7 3", 2.0),
+      ("7 % 3", 1.0),
+      ("PI", std::f32::consts::PI),
+    ] {
+      match run_result(input) {
+        Ok(Some(Value::Num(num))) => { assert!((num - expected).abs() < 1e-5, "{} = {}", input, num); },
+        other => { panic!("{} = {:?}", input, other); },
+      }
+    }
+    for input in &["SQRT -1", "LN 0", "1 / 0", "5 % 0", "REMAINDER 1 0", "POWER -8 0.5", "RANDOM 0"] {
+      assert!(run_result(input).is_err(), "{}", input);
+    }
+    for (input, expected) in &[
+      ("TAN 90", "TAN doesn't like 90 as input"),
+      ("TAN -270", "TAN doesn't like -270 as input"),
+      ("POWER -8 0.5", "POWER doesn't like -8 as input"),
+      ("POWER 0 -1", "POWER doesn't like 0 as input"),
+      ("POWER 10 100", "POWER doesn't like 100 as input"),
+      ("MODULO 7 0", "MODULO doesn't like 0 as input"),
+    ] {
+      assert_eq!(*expected, run_error(input), "{}", input);
+    }
+    for _ in 0 .. 20 {
+      match run_result("RANDOM 3") {
+        Ok(Some(Value::Num(num))) => { assert!(num == 0.0 || num == 1.0 || num == 2.0); },
+        other => { panic!("RANDOM 3 = {:?}", other); },
+      }
     }
   }
 
-  fn get_word(&mut self, ast_node: &AST) -> Result<WordType, String> {
-    match self.eval(ast_node)? {
-      AST::Word(word) => { Ok(word) },
-      _ => { Err(format!("Expr doesn't evaluate to a word {:?}", ast_node)) }
+  #[test]
+  fn test_and_or_not() {
+    for (input, expected) in &[
+      ("AND \"TRUE \"FALSE", "FALSE"),
+      ("AND (1 < 2) (2 > 1)", "TRUE"),
+      ("(AND 1 < 2 2 < 3 3 < 4)", "TRUE"),
+      ("(AND \"TRUE \"TRUE \"FALSE)", "FALSE"),
+      ("OR \"FALSE 1 = 1", "TRUE"),
+      ("(OR \"FALSE \"FALSE \"FALSE)", "FALSE"),
+      ("NOT 1 = 2", "TRUE"),
+      ("NOT AND \"TRUE \"TRUE", "FALSE"),
+    ] {
+      assert_eq!(Ok(Some(word(expected))), run_result(input), "{}", input);
     }
+    assert_eq!("AND doesn't like 1 as input",
+               run_error("AND 1 \"TRUE"));
+    assert!(run_result("NOT [TRUE]").is_err());
   }
 
-  fn get_next_number(&mut self) -> Result<NumType, String> {
-    let next_ast = self.eval_next_expr()?;
-    self.get_number(&next_ast)
+  #[test]
+  fn test_variadic_calls() {
+    assert_eq!(Ok(Some(list(&[Value::Num(1.0), Value::Num(2.0), Value::Num(3.0), Value::Num(4.0)]))),
+               run_result("(LIST 1 2 3 4)"));
+    assert_eq!(Ok(Some(list(&[Value::Num(1.0)]))), run_result("(LIST 1)"));
+    assert_eq!(Ok(Some(list(&[Value::Num(1.0), Value::Num(2.0), Value::Num(3.0)]))),
+               run_result("(SE 1 [2] 3)"));
+    assert_eq!(Ok(Some(word("ABC"))), run_result("(WORD \"A \"B \"C)"));
+    assert_eq!(Ok(Some(Value::Num(3.0))), run_result("TO F :A :B\nOP :A + :B\nEND\n(F 1 2)"));
+    assert_eq!(Ok(Some(word("NO"))), run_result("(IF 1 > 2 [\"YES] [\"NO])"));
+    assert_eq!("Too many inputs to FD",
+               run_error("(FD 10 20)"));
+    assert_eq!("Not enough inputs to FD",
+               run_error("(FD)"));
+    assert_eq!("Not enough inputs to LIST",
+               run_error("LIST 1"));
+    assert_eq!("Too many inputs to F",
+               run_error("TO F :A\nOP :A\nEND\n(F 1 2)"));
+    assert_eq!("TO doesn't like 5 as input", run_error("TO F :A 5"));
+    assert_eq!("TO doesn't like :A as input", run_error("TO :A"));
   }
 
-  fn get_next_list(&mut self) -> Result<ListType, String> {
-    let next_ast = self.eval_next_expr()?;
-    self.get_list(&next_ast)
+  #[test]
+  fn test_parsed_calls() {
+    // Procedures can call ones which are defined after them, redefining reparses the callers.
+    let procedures = "TO A\nB 10\nEND\nTO B :X\nFD :X\nEND\n";
+    run_test(&format!("{}A", procedures), CON!((0.0, 0.0), (0.0, 10.0)));
+    assert_eq!("Not enough inputs to B in A",
+               run_error(&format!("{}TO B :X :Y\nFD :X\nEND\nA", procedures)));
+    assert_eq!("I don't know how to C in A",
+               run_error("TO A\nC\nEND\nA"));
+    // Lists are data until they're run.
+    assert_eq!(Ok(Some(word("FD"))), run_result("FIRST [FD 10]"));
+    assert_eq!(Ok(Some(word(":A*2"))), run_result("LAST [FD :A*2]"));
+    run_test("MAKE \"A 5\nREPEAT 1 [FD :A*2]", CON!((0.0, 0.0), (0.0, 10.0)));
   }
 
-  fn get_next_word(&mut self) -> Result<String, String> {
-    let next_ast = self.eval_next_expr()?;
-    self.get_word(&next_ast)
+  #[test]
+  fn test_tail_calls() {
+    // Deep enough to overflow the Rust stack if the calls nested.
+    let count_up = "TO COUNT.UP :N\nIF :N = 0 [STOP]\nMAKE \"C :C + 1\nCOUNT.UP :N - 1\nEND\n";
+    assert_eq!(Ok(Some(Value::Num(20000.0))),
+               run_result(&format!("{}MAKE \"C 0\nCOUNT.UP 20000\n:C", count_up)));
+    let down = "TO DOWN :N\nIF :N = 0 [OP \"DONE]\nOP DOWN :N - 1\nEND\n";
+    assert_eq!(Ok(Some(word("DONE"))), run_result(&format!("{}DOWN 20000", down)));
+    // The called procedure still sees the variables of the one which called it.
+    assert_eq!(Ok(Some(Value::Num(6.0))),
+               run_result("TO G\nOP :A + 1\nEND\nTO F :A\nOP G\nEND\nF 5"));
+    assert_eq!("G didn't output to OP in F: OP G",
+               run_error("TO G\nFD 1\nEND\nTO F\nOP G\nEND\nF"));
+    assert_eq!("You don't say what to do with 1 in F: G",
+               run_error("TO G\nOP 1\nEND\nTO F\nG\nEND\nF"));
+    // A chain of tail calls runs in one frame, with LOCALs and inputs of other names too.  FRAMES
+    // outputs the number of frames, the globals are one of them.
+    let loops = "TO F :N\nLOCALMAKE \"X :N\nMAKE \"D LPUT FRAMES :D\nIF :N = 0 [STOP]\nF :N - 1\nEND\n\
+                 TO A :N\nMAKE \"D LPUT FRAMES :D\nIF :N = 0 [STOP]\nB :N - 1\nEND\n\
+                 TO B :M\nLOCAL \"Y\nMAKE \"D LPUT FRAMES :D\nA :M - 1\nEND\nMAKE \"D []";
+    for mode in &[Mode::Compile, Mode::Interpret] {
+      for call in &["F 100", "A 100"] {
+        let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+        evaluator.set_mode(*mode);
+        let frames = Builtin::new(Arity::fixed(0), |evaluator, _inputs| {
+          Ok(Some(Value::Num(evaluator.stack_vars.len() as NumType)))
+        });
+        evaluator.insert_builtin(&["FRAMES".to_string()], frames);
+        evaluator.parser.set_arity("FRAMES", Arity::fixed(0));
+        for line in loops.lines().chain(std::iter::once(*call)) {
+          evaluator.feed(line);
+        }
+        assert_eq!(Ok(list(&vec![Value::Num(2.0); 101])), evaluator.get("D"), "{}", call);
+        assert_eq!(1, evaluator.stack_vars.len());
+      }
+    }
   }
 
-  // TODO: REPEAT 4 [4] complains about what to do with 4, while EVAL [4] just returns [4].
-  // EVAL [1 2 FD 50 3] should return [1 2 3]
-  fn eval_list(&mut self, list: &ListType) -> Result<(), String> {
-    self.stack_expr.push(list.clone());
-    let mut ret = Ok(());
-    while let Some(expr) = self.current_expr_list().pop_front() {
-      match self.eval(&expr) {
-        Ok(AST::None) => {},
-        Err(e) => {
-          ret = Err(e);
-          break;
-        },
-        Ok(other) => {
-          ret = Err(format!("You don't say what to do with the output of {:?}", other));
-          break;
-        }
+  #[test]
+  fn test_max_depth() {
+    let fractal = "TO FRACTAL :N\nIF :N = 0 [OP 0]\nOP 1 + FRACTAL :N - 1\nEND";
+    for mode in &[Mode::Compile, Mode::Interpret] {
+      let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+      evaluator.set_mode(*mode);
+      evaluator.set_max_depth(20);
+      evaluator.printed = Some(Vec::new());
+      // The list of the IF at the bottom is a level too.
+      for line in fractal.lines().chain(vec!["FRACTAL 30", "PR FRACTAL 18", "PR FRACTAL 19"]) {
+        evaluator.feed(line);
       }
+      let mut printed = vec!["Stack overflow in FRACTAL: OP 1 + FRACTAL :N - 1".to_string()];
+      printed.extend(vec!["  called from FRACTAL: OP 1 + FRACTAL :N - 1".to_string(); 5]);
+      printed.extend(vec!["  ... and 14 more".to_string(), "18".to_string()]);
+      printed.push("Stack overflow in FRACTAL: IF :N = 0 [OP 0]".to_string());
+      printed.extend(vec!["  called from FRACTAL: OP 1 + FRACTAL :N - 1".to_string(); 5]);
+      printed.push("  ... and 14 more".to_string());
+      assert_eq!(Some(printed), evaluator.printed);
+      assert_eq!((0, 1), (evaluator.depth, evaluator.stack_vars.len()));
     }
-    self.stack_expr.pop();
-    return ret;
   }
 
-  fn define_user_function(&mut self, ast_node: &AST) -> Result<bool, String> {
-    // Already started defining.
-    if self.name != "" {
-      if let AST::ExprLine(expr_list) = ast_node {
-        if let Some(AST::Function(name)) = expr_list.front() {
-          if name == "TO" {
-            return Err(format!("TO inside of function definition {}", self.name));
-          } else if name == "END" {
-            // End of function definition, save it.
-            let name = std::mem::replace(&mut self.name, String::new());
-            let args = std::mem::replace(&mut self.args, ArgsType::new());
-            let lines = std::mem::replace(&mut self.lines, ListType::new());
-            self.user_functions.insert(name, (args, lines));
-          } else {
-            // Collect the line.
-            self.lines.push_back(ast_node.clone());
+  #[test]
+  fn test_max_depth_control_structures() {
+    // Recursing through lists that get run stops at the max depth on the thread sized for it.
+    let programs = [
+      "TO G :N\nIF :N = 0 [OP 0]\nOP FIRST MAP [G ? - 1] (LIST :N)\nEND",
+      "TO G :N\nWHILE [:N > 0] [IF \"TRUE [REPEAT 1 [G :N - 1]] MAKE \"N 0]\nEND",
+      "TO G :N\nREPEAT 1 [CATCH \"X [RUN [IF \"TRUE [G :N - 1]]]]\nEND",
+      "TO G :N\nIF :N = 0 [OP 0]\nOP 1 + (FIRST (LIST (1 + G :N - 1) 2))\nEND",
+    ];
+    let thread = spawn(DEFAULT_MAX_DEPTH, move || {
+      for program in programs.iter() {
+        for mode in &[Mode::Compile, Mode::Interpret] {
+          let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+          evaluator.set_mode(*mode);
+          evaluator.printed = Some(Vec::new());
+          for line in program.lines().chain(vec!["G 100000", "PR \"AFTER"]) {
+            evaluator.feed(line);
           }
+          let printed = evaluator.printed.take().unwrap();
+          assert!(printed[0].starts_with("Stack overflow in G"), "{:?}", printed);
+          assert_eq!(Some("AFTER"), printed.last().map(String::as_str));
+          assert_eq!((0, 1, 0), (evaluator.depth, evaluator.stack_vars.len(), evaluator.catch_tags.len()));
         }
       }
-      return Ok(true);
-    }
-    if ast_node != &AST::Function("TO".to_string()) {
-      return Ok(false);
-    }
-    match self.current_expr_list().pop_front() {
-      Some(AST::Function(name)) => {
-        if self.builtin_functions.contains_key(&name) {
-          return Err(format!("{} is already in use. Try a different name.", name));
-        }
-        let mut args = ArgsType::new();
-        while let Some(arg) = self.current_expr_list().pop_front() {
-          if let AST::Var(arg) = arg {
-            args.push(arg);
-          } else {
-            return Err(format!("The procedure TO does not like {:?} as input.", arg));
-          }
+    });
+    thread.unwrap().join().unwrap();
+    // Recursion which isn't a tail call goes as deep as the max depth allows.
+    let thread = spawn(10000, || {
+      for mode in &[Mode::Compile, Mode::Interpret] {
+        let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+        evaluator.set_mode(*mode);
+        evaluator.set_max_depth(10000);
+        evaluator.printed = Some(Vec::new());
+        for line in "TO SUM :N\nIF :N = 0 [OP 0]\nOP :N + SUM :N - 1\nEND\nPR SUM 4000".lines() {
+          evaluator.feed(line);
         }
-        self.name = name;
-        self.args = args;
-      },
-      Some(_) => {
-        return Err(format!("The procedure TO needs a name as its first input."));
-      },
-      None => {
-        return Err(format!("TO needs more input(s)."));
+        assert_eq!(Some(vec!["8002000".to_string()]), evaluator.printed);
       }
-    }
-    return Ok(true);
+    });
+    thread.unwrap().join().unwrap();
+    assert_eq!(None, stack_size(100000));
+    assert!(spawn(usize::MAX, || ()).is_err());
   }
 
-  fn eval_next_expr(&mut self) -> Result<AST, String> {
-    let next_ast = self.current_expr_list().pop_front();
-    // TODO: if let Some(ast) = next_ast { ...
-    match next_ast {
-      Some(ast) => {
-        return self.eval(&ast);
-      },
-      None => {
-        return Err(format!("Need more input(s)."));
+  #[test]
+  fn test_compiled_in_place() {
+    // IF, REPEAT and WHILE with their lists compiled into the procedure fail like the builtins.
+    assert_eq!("IF doesn't like X as input in F: IF \"X [FD 1]",
+               run_error("TO F\nIF \"X [FD 1]\nEND\nF"));
+    assert_eq!("REPEAT doesn't like X as input in F: REPEAT \"X [FD 1]",
+               run_error("TO F\nREPEAT \"X [FD 1]\nEND\nF"));
+    assert_eq!("Condition [FD 1] doesn't output TRUE or FALSE in F: WHILE [FD 1] [FD 1]",
+               run_error("TO F\nWHILE [FD 1] [FD 1]\nEND\nF"));
+    assert_eq!("the condition doesn't like X as input in F: UNTIL [\"X] [FD 1]",
+               run_error("TO F\nUNTIL [\"X] [FD 1]\nEND\nF"));
+    assert_eq!("You don't say what to do with 5 in F: IFELSE \"TRUE [5] [6]",
+               run_error("TO F\nIFELSE \"TRUE [5] [6]\nEND\nF"));
+    assert_eq!("You don't say what to do with 1 in F: IF \"TRUE [G]",
+               run_error("TO G\nOP 1\nEND\nTO F\nIF \"TRUE [G]\nEND\nF"));
+    assert_eq!("I don't know how to G in F: G",
+               run_error("TO G\nFD 1\nEND\nTO F\nERASE \"G\nG\nEND\nF"));
+    let lists = |items: &[&[NumType]]| {
+      list(&items.iter().map(|nums| list(&nums.iter().map(|num| Value::Num(*num)).collect::<Vec<_>>()))
+                 .collect::<Vec<_>>())
+    };
+    assert_eq!(Ok(Some(lists(&[&[1.0, 2.0, 1.0, 1.0, 2.0, 2.0], &[3.0, -1.0]]))), run_result(
+        "TO F\nMAKE \"L []\nMAKE \"N 0\nLABEL \"AGAIN\nMAKE \"N :N + 1\n\
+         REPEAT 2.7 [IF :N < 3 [GO \"AGAIN] REPEAT 2 [MAKE \"L LPUT # :L] MAKE \"L LPUT # :L]\n\
+         OP LIST :L LIST :N #\nEND\nF"));
+    // Whichever way the lists are left, the stacks are back where they were.
+    for mode in &[Mode::Compile, Mode::Interpret] {
+      let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+      evaluator.set_mode(*mode);
+      evaluator.printed = Some(Vec::new());
+      let program = "TO F :N\nREPEAT 3 [WHILE [\"TRUE] [IF :N = 1 [STOP] IF :N = 2 [OP 2] FD \"X]]\nEND\n\
+                     F 1\nPR F 2\nF 3";
+      for line in program.lines() {
+        evaluator.feed(line);
       }
+      assert_eq!(Some(vec!["2".to_string(), "FD doesn't like X as input in F: \
+                           REPEAT 3 [WHILE [\"TRUE] [IF :N = 1 [STOP] IF :N = 2 [OP 2] FD \"X]]".to_string()]),
+                 evaluator.printed);
+      assert_eq!((0, 0, 0), (evaluator.depth, evaluator.stack_repcount.len(), evaluator.stack_values.len()));
     }
   }
 
-  fn eval_builtin_function(&mut self, name: &str) -> Result<AST, String> {
-    let closure = self.builtin_functions.get(name).unwrap().clone();
-    return closure(self);
-  }
-
-  fn eval_user_function(&mut self, name: &str) -> Result<AST, String> {
-    let args;
-    let lines;
-    // TODO: If user_functions was using Rc or RefCell, maybe I wouldn't have the problem here.
-    match self.user_functions.get(name) {
-      Some((_args, _lines)) => {
-        args = _args.clone();
-        lines = _lines.clone();
-      },
-      _ => { panic!("Invalid eval_user_function invocation {}", name); }
-    }
-    let mut local_vars: HashMap<String, AST> = HashMap::new();
-    // Setup the args as local vars.
-    for arg in args {
-      local_vars.insert(arg.clone(), self.eval_next_expr()?);
-    }
-    self.stack_vars.push(local_vars);
-    // TODO: Probably don't need this push here?
-    self.stack_expr.push(VecDeque::new());
-    let mut ret = AST::None;
-    // Run the lines.
-    let mut err = None;
-    for line in lines {
-      match self.eval(&line) {
-        Ok(result) => {
-          ret = result;
-        },
-        e @ Err(_) => {
-          err = Some(e);
-          break;
-        },
-      }
-      match ret {
-        AST::FunctionReturn(ast) => {
-          ret = *ast;
-          break;
+  #[test]
+  fn test_errors() {
+    let procedures = "TO DIJ :A\nFD :A\nEND\nTO MAIN\nDIJ [1 2]\nPR \"DONE\nEND\n";
+    assert_eq!("FD doesn't like [1 2] as input in DIJ: FD :A", run_error(&format!("{}MAIN", procedures)));
+    for mode in &[Mode::Compile, Mode::Interpret] {
+      match run_result_in(*mode, &format!("{}MAIN", procedures)) {
+        Err(Unwind::Error(err)) => {
+          assert_eq!(7, err.code());
+          assert_eq!(Some(&list(&[Value::Num(1.0), Value::Num(2.0)])), err.value());
+          assert_eq!(vec![Place { procedure: "MAIN".to_string(), line: Some("DIJ [1 2]".to_string()) }],
+                     err.callers);
         },
-        AST::None => {},
-        _ => {
-          err = Some(Err(format!(
-              "You don't say what to do with the output of {:?}\n\
-               In function {}\n\
-               Statement   {:?}", ret, name, line)));
-        }
+        other => { panic!("{:?}", other); },
       }
     }
-    self.stack_vars.pop();
-    self.stack_expr.pop();
-    match err {
-      None => { Ok(ret) },
-      Some(err) => { err }
-    }
+    // Lists are part of the line running them, continued lines are one line.
+    assert_eq!("FD doesn't like X as input in SQ: REPEAT 4 [FD \"X RT 90]",
+               run_error("TO SQ\nREPEAT 4 [FD \"X RT 90]\nEND\nSQ"));
+    assert_eq!("FD doesn't like X as input in F: FD 1 FD \"X",
+               run_error("TO F\nFD 1\\\nFD \"X\nEND\nF"));
   }
 
-  fn eval(&mut self, ast_node: &AST) -> Result<AST, String> {
-    // self.print_locals();
-    // self.print_globals();
-    println!("{:?}", ast_node);
-    if self.define_user_function(ast_node)? {
-      // We're currently defining a function.
-      return Ok(AST::None);
+  #[test]
+  fn test_unused_output() {
+    assert_eq!("You don't say what to do with 1", run_error("1 FD 10"));
+    let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+    evaluator.printed = Some(Vec::new());
+    for line in &["1 + 2", "[PR \"B]", "IF \"TRUE [PR \"A] [PR \"B]", "PR [-1]", "-1"] {
+      evaluator.feed(line);
     }
-    let mut ret = AST::None;
-    match ast_node {
-      AST::Function(name) => {
-        if self.builtin_functions.contains_key(name) {
-          ret = self.eval_builtin_function(name)?;
-        } else if self.user_functions.contains_key(name) {
-          ret = self.eval_user_function(name)?;
-        } else {
-          return Err(format!("Unknown function {:?}", name));
-        }
-      },
-      // TODO: Type that pushes during construction, and pops during destruction.
-      AST::ExprLine(expr_list) => {
-        self.stack_expr.push(expr_list.clone());
-        // defer! {
-        //   self.stack_expr.pop();
-        // }
-        while let Some(expr) = self.current_expr_list().pop_front() {
-          ret = self.eval(&expr)?;
-          if ret != AST::None {
-            break;
-          }
-        }
-        self.stack_expr.pop();
-      },
-      // TODO: Builtin functions behave differently if they open Parens.
-      AST::Parens(expr_list) => {
-        // Evaluates only the first expr and returns result (if any).  If the expression list is
-        // empty, returns the empty list.
-        if expr_list.is_empty() {
-          ret = AST::List(ListType::new());
-        } else {
-          // TODO: Can this be simplified to just (probably) - nope, needs recursive parsing with pushing onto stack:
-          // ret = self.eval(&expr_list[0])?;
-          self.stack_expr.push(expr_list.clone());
-          let next_expr = self.current_expr_list().pop_front().unwrap();
-          ret = self.eval(&next_expr)?;
-          self.stack_expr.pop();
-        }
-      },
-      AST::Var(var_name) => {
-        // TODO: Should replace stack_vars here with local_vars?
-        if let Some(ast) = self.stack_vars.last().unwrap().get(var_name) {
-          ret = ast.clone();
-        } else if let Some(ast) = self.vars.get(var_name) {
-          ret = ast.clone();
-        } else {
-          return Err(format!(":{} is not a Logo name.", var_name));
-        }
-      },
-      AST::Num(num) => {
-        ret = AST::Num(*num);
-      },
-      AST::List(list) => {
-        ret = AST::List(list.clone());
-      },
-      AST::Word(string) => {
-        ret = AST::Word(string.clone());
-      },
-      AST::Negation(box_operand) => {
-        let operand = self.get_number(box_operand)?;
-        ret = AST::Num(-operand);
-      },
-      AST::Comparison(operator, left_box, right_box) => {
-        let left = self.eval(left_box)?;
-        let right = self.eval(right_box)?;
-        let mut one_word = false;
-        for word in vec![&left, &right] {
-          match word {
-            AST::Num(_) => {},
-            AST::Word(_) => { one_word = true; },
-            _ => { return Err(format!("The comparison procedure needs a name or number.")); }
-          }
-        }
-        let result;
-        if !one_word {
-          let left = self.get_number(&left);
-          let right = self.get_number(&right);
-          result = match operator {
-            Token::Less => { left < right },
-            Token::LessEq => { left <= right },
-            Token::Greater => { left > right },
-            Token::GreaterEq => { left >= right },
-            Token::Equal => { left == right },
-            _ => {
-              panic!("Unknown comparison operator {:?}", operator);
-            }
-          };
-        } else {
-          let left = match left {
-            AST::Num(num) => {
-              format!("{}", num)
-            },
-            AST::Word(string) => { string },
-            _ => { unreachable!() }
-          };
-          let right = match right {
-            AST::Num(num) => {
-              format!("{}", num)
-            },
-            AST::Word(string) => { string },
-            _ => { unreachable!() }
-          };
-          result = match operator {
-            Token::Less => { left < right },
-            Token::LessEq => { left <= right },
-            Token::Greater => { left > right },
-            Token::GreaterEq => { left >= right },
-            Token::Equal => { left == right },
-            _ => {
-              panic!("Unknown comparison operator {:?}", operator);
-            }
-          };
-        }
-        ret = AST::Word( (if result { "TRUE" } else { "FALSE" }).to_string() );
-      },
-      AST::Binary(operator, left_box, right_box) => {
-        let left = self.get_number(left_box)?;
-        let right = self.get_number(right_box)?;
-        let result = match operator {
-          Token::Plus => { left + right },
-          Token::Minus => { left - right },
-          Token::Multiply => { left * right },
-          Token::Divide => { left / right },
-          _ => {
-            panic!("Unknown binary operator {:?}", operator);
-          }
-        };
-        ret = AST::Num(result);
-      },
-      AST::Nary(operator, expr_list_orig) => {
-        let mut expr_list = expr_list_orig.clone();
-        let mut result;
-        if operator == &Token::Plus {
-          result = 0.0;
-        } else if operator == &Token::Multiply {
-          result = 1.0;
-        } else {
-          panic!("Unknown prefix operator {:?}", operator);
-        }
-        while let Some(operand) = expr_list.pop_front() {
-          let operand = self.get_number(&operand)?;
-          if operator == &Token::Plus {
-            result += operand;
-          } else if operator == &Token::Multiply {
-            result *= operand;
-          } else {
-          }
-        }
-        ret = AST::Num(result);
-      },
-      _x => {
-        println!("Unimplemented eval AST {:?}", _x);
+    assert_eq!(Some(["You don't say what to do with 3", "You don't say what to do with [PR \"B]", "A", "-1",
+                     "You don't say what to do with -1"].iter().map(|line| line.to_string()).collect()),
+               evaluator.printed);
+  }
+
+  #[test]
+  fn test_catch_throw() {
+    run_test("CATCH \"DONE [FD 10 THROW \"DONE FD 20]\nFD 5", CON!((0.0, 0.0), (0.0, 10.0), (0.0, 15.0)));
+    run_test("TO F :N\nIF :N = 0 [THROW \"OUT]\nFD 10\nF :N - 1\nPR :N\nEND\nCATCH \"OUT [F 2]",
+             CON!((0.0, 0.0), (0.0, 10.0), (0.0, 20.0)));
+    assert_eq!(Ok(Some(Value::Num(5.0))), run_result("CATCH \"X [(THROW \"X 5)]"));
+    assert_eq!(Ok(Some(Value::Num(3.0))), run_result("CATCH \"X [CATCH \"Y [(THROW \"X 3)] 4]"));
+    assert_eq!(Ok(None), run_result("CATCH \"X [THROW \"X]"));
+    assert_eq!("Can't find catch tag for X", run_error("THROW \"X"));
+    assert_eq!("Can't find catch tag for X", run_error("CATCH \"Y [THROW \"X]"));
+    // Errors get caught by CATCH "ERROR, ERROR outputs the last one only once.
+    assert_eq!(Ok(Some(list(&[
+                 Value::Num(7.0), word("FD doesn't like [1 2] as input"), word("DIJ"), word("FD :A")]))),
+               run_result("TO DIJ :A\nFD :A\nEND\nCATCH \"ERROR [DIJ [1 2]]\nERROR"));
+    assert_eq!(Ok(Some(list(&[]))), run_result("CATCH \"ERROR [FD [1 2]]\nMAKE \"E ERROR\nERROR"));
+    assert_eq!(Ok(Some(list(&[Value::Num(35.0), word("BAD INPUT"), list(&[]), list(&[])]))),
+               run_result("CATCH \"ERROR [(THROW \"ERROR [BAD INPUT])]\nERROR"));
+    assert_eq!("Throw \"Error", run_error("THROW \"ERROR"));
+    for mode in &[Mode::Compile, Mode::Interpret] {
+      let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+      evaluator.set_mode(*mode);
+      evaluator.set_max_depth(20);
+      evaluator.printed = Some(Vec::new());
+      let lines = "TO DOWN :N\nOP 1 + DOWN :N - 1\nEND\nCATCH \"ERROR [PR DOWN 5]\nPR FIRST ERROR\n\
+                   MAKE \"ERRACT [PR [OOPS:] PR ITEM 2 ERROR]\nFD [1 2]\nTHROW \"TOPLEVEL\nPR \"DONE";
+      for line in lines.lines() {
+        evaluator.feed(line);
       }
+      assert_eq!(Some(["2", "OOPS:", "FD doesn't like [1 2] as input", "DONE"].iter()
+                          .map(|line| line.to_string()).collect()),
+                 evaluator.printed);
+      assert_eq!((0, 1, 0), (evaluator.depth, evaluator.stack_vars.len(), evaluator.catch_tags.len()));
     }
-    return Ok(ret);
   }
 
-  pub fn feed(&mut self, input: &str) {
-    // println!("{:?}", input);
-    let ast;
-    match self.parser.parse(input) {
-      Ok(val) => {
-        ast = val;
-        // println!("{:?}", ast);
-        // parser::rek_print(&ast, "".to_string());
-      },
-      Err(err) => {
-        println!("Parsing error: {:?}", err);
-        return;
-      },
-    }
-    let result = self.eval(&ast);
-    if result != Ok(AST::None) {
-      println!("{}", format!("Eval: {:?}", result).replace("([", "[").replace("])", "]"));
-      // TODO: Occasionally try to run the following to make sure nothing is being lost from ast.
-      // println!("{}", format!("Eval: {:?}", self.eval(&ast)).replace("([", "[").replace("])", "]"));
-    }
-    if self.stack_expr.len() > 0 {
-      println!("stack_expr remainder: {:?}", self.stack_expr);
-    }
-    assert!(self.stack_vars.len() > 0);
-    assert_eq!(0, self.stack_vars[0].len());
+  #[test]
+  fn test_run_apply() {
+    run_test("MAKE \"CMDS [FD 10 RT 90]\nRUN LPUT 5 LPUT \"FD :CMDS",
+             CON!((0.0, 0.0), (0.0, 10.0), (5.0, 10.0)));
+    run_test("TO F\nRUN [OP 20]\nFD 5\nEND\nFD F", CON!((0.0, 0.0), (0.0, 20.0)));
+    assert_eq!(Ok(Some(Value::Num(3.0))), run_result("RUN [1 + 2]"));
+    assert_eq!(Ok(Some(list(&[Value::Num(3.0)]))), run_result("RUNRESULT [1 + 2]"));
+    assert_eq!(Ok(Some(list(&[]))), run_result("RUNRESULT [MAKE \"A 1]"));
+    assert_eq!("You don't say what to do with 1", run_error("RUN [1 2]"));
+    assert_eq!(Ok(Some(list(&[Value::Num(1.0), Value::Num(2.0)]))),
+               run_result("APPLY \"FPUT [1 [2]]"));
+    assert_eq!(Ok(Some(Value::Num(12.0))), run_result("APPLY [? * ?2] [3 4]"));
+    assert_eq!(Ok(Some(Value::Num(6.0))), run_result("(INVOKE [? + (? 2) + ?3] 1 2 3)"));
+    assert_eq!(Ok(Some(Value::Num(-1.0))), run_result("(INVOKE [[A B] :A - :B] 3 4)"));
+    assert_eq!(Ok(Some(Value::Num(10.0))), run_result("TO F :X\nOP :X * 2\nEND\nINVOKE \"F 5"));
+    assert_eq!(Ok(Some(Value::Num(9.0))), run_result("APPLY [APPLY [? * ?] (LIST ?)] [3]"));
+    assert_eq!("Not enough inputs to FPUT", run_error("APPLY \"FPUT [1]"));
+    assert_eq!("Too many inputs to APPLY", run_error("APPLY [[A] :A] [1 2]"));
+    assert_eq!("?2 has no value", run_error("APPLY [?2] [1]"));
+    assert_eq!("? has no value", run_error("PR ?"));
   }
-}
 
+  #[test]
+  fn test_templates() {
+    let nums = |nums: &[NumType]| {
+      list(&nums.iter().map(|&num| Value::Num(num)).collect::<Vec<Value>>())
+    };
+    assert_eq!(Ok(Some(nums(&[2.0, 4.0, 6.0]))), run_result("MAP [? * 2] [1 2 3]"));
+    assert_eq!(Ok(Some(nums(&[11.0, 22.0]))), run_result("(MAP [[A B] :A + :B] [1 2] [10 20])"));
+    assert_eq!(Ok(Some(nums(&[1.0, 2.0]))), run_result("MAP \"FIRST [[1 3] [2 4]]"));
+    assert_eq!(Ok(Some(word("BBC"))), run_result("MAP [IFELSE ? = \"A [\"B] [?]] \"ABC"));
+    assert_eq!(Ok(Some(nums(&[1.0, 2.0]))), run_result("MAP [#] [A B]"));
+    assert_eq!(Ok(Some(nums(&[2.0, 4.0]))), run_result("FILTER [0 = REMAINDER ? 2] [1 2 3 4]"));
+    assert_eq!(Ok(Some(word("435"))), run_result("FILTER [? > 2] 1435"));
+    assert_eq!(Ok(Some(Value::Num(3.0))), run_result("FIND [? > 2] [1 3 5]"));
+    assert_eq!(Ok(Some(list(&[]))), run_result("FIND [? > 5] [1 3 5]"));
+    assert_eq!(Ok(Some(Value::Num(-2.0))), run_result("REDUCE [?1 - ?2] [1 2 3 4]"));
+    assert_eq!(Ok(Some(word("ABC"))), run_result("REDUCE \"WORD [A B C]"));
+    assert_eq!(Ok(Some(list(&[word("AX"), word("AY"), word("BX"), word("BY")]))),
+               run_result("CROSSMAP [WORD ?1 ?2] [[A B] [X Y]]"));
+    assert_eq!(Ok(Some(nums(&[11.0, 12.0, 21.0, 22.0]))),
+               run_result("(CROSSMAP [?1 + ?2] [10 20] [1 2])"));
+    assert_eq!(Ok(Some(Value::Num(32.0))), run_result("CASCADE 5 [? * 2] 1"));
+    assert_eq!(Ok(Some(Value::Num(128.0))), run_result("CASCADE [? > 100] [? * 2] 1"));
+    // Fibonacci, the 10th number.
+    assert_eq!(Ok(Some(Value::Num(55.0))), run_result("(CASCADE 10 [?1 + ?2] 0 [?1] 1)"));
+    assert_eq!(Ok(Some(Value::Num(120.0))), run_result("(CASCADE 5 [?1 * ?2] 1 [?2 + 1] 1 [?1])"));
+    run_test("FOREACH [10 20] [FD ?]", CON!((0.0, 0.0), (0.0, 10.0), (0.0, 30.0)));
+    run_test("(FOREACH [10 20] [1 2] [[A B] FD :A * :B])",
+             CON!((0.0, 0.0), (0.0, 10.0), (0.0, 50.0)));
+    run_test("TO F :L\nFOREACH :L [IF ? > 1 [STOP] FD #]\nFD 10\nEND\nF [1 2 3]",
+             CON!((0.0, 0.0), (0.0, 1.0)));
+    assert_eq!("[FD ?] didn't output to MAP", run_error("MAP [FD ?] [1]"));
+    assert_eq!("FILTER doesn't like 5 as input", run_error("FILTER [5] [1]"));
+    assert_eq!("REDUCE doesn't like [] as input", run_error("REDUCE \"WORD []"));
+    assert_eq!("You don't say what to do with 1", run_error("FOREACH [1] [?]"));
+  }
 
+  #[test]
+  fn test_arrays() {
+    assert_eq!(Ok(Some(Value::Num(2.0))), run_result("ITEM 2 {1 2 3}"));
+    assert_eq!(Ok(Some(Value::Num(1.0))), run_result("ITEM 0 {1 2 3}@0"));
+    assert_eq!(Ok(Some(Value::Num(3.0))), run_result("COUNT ARRAY 3"));
+    assert_eq!(Ok(Some(list(&[Value::Num(5.0), list(&[])]))),
+               run_result("MAKE \"A (ARRAY 2 0)\nMAKE \"B :A\nSETITEM 0 :B 5\nARRAYTOLIST :A"));
+    // A literal in a procedure is a new array on every call.
+    assert_eq!(Ok(Some(Value::Num(1.0))),
+               run_result("TO F\nMAKE \"A {1 2}\nSETITEM 1 :A 5\nEND\nF\nF\nITEM 1 {1 2}"));
+    assert_eq!(Ok(Some(word("X"))),
+               run_result("MAKE \"M MDARRAY [2 3]\nMDSETITEM [2 3] :M \"X\nMDITEM [2 3] :M"));
+    assert_eq!(Ok(Some(Value::Num(3.0))), run_result("COUNT MDITEM [1] MDARRAY [2 3]"));
+    assert_eq!(Ok(Some(Value::Num(7.0))), run_result("ITEM -1 (LISTTOARRAY [7 8] -1)"));
+    assert_eq!(Ok(Some(word("TRUE"))), run_result("ARRAY? {}"));
+    assert_eq!(Ok(Some(word("FALSE"))), run_result("EQUAL? {1} {1}"));
+    run_test("MAKE \"A {10 [20]}\nFD ITEM 1 :A FD FIRST ITEM 2 :A",
+             CON!((0.0, 0.0), (0.0, 10.0), (0.0, 30.0)));
+    run_test("MAKE \"A ARRAY 2\nREPEAT 2 [SETITEM # :A # * 10]\nFOREACH ARRAYTOLIST :A [FD ?]",
+             CON!((0.0, 0.0), (0.0, 10.0), (0.0, 30.0)));
+    assert_eq!("ITEM doesn't like 3 as input", run_error("ITEM 3 {1 2}@0"));
+    assert_eq!("ITEM doesn't like 1.5 as input", run_error("ITEM 1.5 {A B}"));
+    assert_eq!("ITEM doesn't like 1.5 as input", run_error("ITEM 1.5 [A B]"));
+    assert_eq!("SETITEM doesn't like 2.5 as input", run_error("SETITEM 2.5 {1 2} 3"));
+    // The error holds the array, which isn't equal to the one of the other mode.
+    assert_eq!(Ok(Some(word("SETITEM doesn't like [{1}] as input"))),
+               run_result("MAKE \"A {1}\nCATCH \"ERROR [SETITEM 1 :A (LIST :A)]\nITEM 2 ERROR"));
+    assert_eq!("MDITEM doesn't like 1 as input", run_error("MDITEM [1 1] {1}"));
+  }
 
-#[cfg(test)]
-mod tests {
-  use super::*;
-  use CON;
+  #[test]
+  fn test_plists() {
+    let setup = "PPROP \"ANA \"SCORE 90\nPPROP \"ANA \"CLASS [3 B]\nPPROP \"ANA \"SCORE 95\n";
+    assert_eq!(Ok(Some(Value::Num(95.0))), run_result(&format!("{}GPROP \"ANA \"SCORE", setup)));
+    assert_eq!(Ok(Some(list(&[word("SCORE"), Value::Num(95.0), word("CLASS"),
+                              list(&[Value::Num(3.0), word("B")])]))),
+               run_result(&format!("{}PLIST \"ANA", setup)));
+    assert_eq!(Ok(Some(list(&[word("CLASS"), list(&[Value::Num(3.0), word("B")])]))),
+               run_result(&format!("{}REMPROP \"ANA \"SCORE\nPLIST \"ANA", setup)));
+    assert_eq!(Ok(Some(list(&[]))), run_result(&format!("{}GPROP \"ANA \"AGE", setup)));
+    assert_eq!(Ok(Some(list(&[]))), run_result("REMPROP \"BO \"AGE\nPLIST \"BO"));
+    let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+    evaluator.printed = Some(Vec::new());
+    for line in format!("{}PPROP \"BO \"NAME \"BO\nREMPROP \"ANA \"SCORE\nREMPROP \"ANA \"CLASS\n\
+                        PPROP \"ANA \"AGE 9\nPPS", setup).lines() {
+      evaluator.feed(line);
+    }
+    assert_eq!(Some(vec!["PPROP \"ANA \"AGE 9".to_string(), "PPROP \"BO \"NAME \"BO".to_string()]),
+               evaluator.printed);
+  }
 
-  fn run_test(input: &str, expected: Vec<turtle::Command>) {
-    let graphics_stub = turtle::GraphicsStub::new();
-    let mut evaluator = Evaluator::new(Box::new(graphics_stub.clone()));
-    evaluator.feed(input);
-    // TODO:
-    // Need to check both the graphics stub and the "stdout" (program output) which needs to be captured better.
-    // Also need to be able to check for errors.
-    let actual = (*graphics_stub.invocations).take();
-    println!("{:?}", actual);
-    assert_eq!(expected, actual);
+  #[test]
+  fn test_names() {
+    assert_eq!(Ok(Some(Value::Num(5.0))), run_result("MAKE \"A 5\nMAKE \"P \"A\nTHING :P"));
+    assert_eq!(Ok(Some(Value::Num(6.0))), run_result("NAME 6 \"A\nTHING \"A"));
+    assert_eq!(Ok(Some(Value::Num(2.0))),
+               run_result("TO F :X\nOP THING \"X\nEND\nMAKE \"X 1\nF 2"));
+    assert_eq!(Ok(Some(Value::Num(3.0))),
+               run_result("FOREACH [A B C] [NAME # ?]\nTHING LAST [A B C]"));
+    assert_eq!(Ok(Some(list(&[word("TRUE"), word("FALSE"), word("FALSE")]))),
+               run_result("TO F\nLOCAL \"L\nOP (LIST NAME? \"A NAME? \"B NAME? \"L)\nEND\n\
+                           MAKE \"A 1\nF"));
+    assert_eq!(Ok(Some(list(&[list(&[]), list(&[word("A"), word("B")])]))),
+               run_result("MAKE \"B 1\nMAKE \"A 2\nNAMES"));
+    assert_eq!(Ok(Some(list(&[list(&[]), list(&[word("C")])]))),
+               run_result("MAKE \"B 1\nMAKE \"A 2\nMAKE \"C 3\nERN [A B]\nNAMES"));
+    assert_eq!(Ok(Some(list(&[list(&[]), list(&[])]))), run_result("MAKE \"B 1\nERNS\nNAMES"));
+    // ERN of a local leaves the global.
+    assert_eq!(Ok(Some(Value::Num(1.0))),
+               run_result("TO F :X\nERN \"X\nOP :X\nEND\nMAKE \"X 1\nF 2"));
+    assert_eq!("A has no value in F: OP THING \"A",
+               run_error("TO F\nLOCAL \"A\nOP THING \"A\nEND\nF"));
+    assert_eq!(":A is not a Logo name.", run_error("MAKE \"A 1\nERN \"A\nTHING \"A"));
+    let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+    evaluator.printed = Some(Vec::new());
+    for line in "MAKE \"S \"HI\nMAKE \"L [1 [2]]\nTO F :X\nLOCAL \"Y\nPONS\nEND\nF 5".lines() {
+      evaluator.feed(line);
+    }
+    assert_eq!(Some(["LOCALMAKE \"X 5", "LOCAL \"Y", "MAKE \"L [1 [2]]", "MAKE \"S \"HI"].iter()
+                         .map(|line| line.to_string()).collect()),
+               evaluator.printed);
   }
 
   #[test]
-  fn test_fd() {
-    run_test("FD 50", CON!((0.0, 0.0), (0.0, 50.0)));
+  fn test_workspace() {
+    let run = |input: &str| {
+      let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+      evaluator.printed = Some(Vec::new());
+      let defines = "TO SQUARE :A ; a comment\n  ; draws it\n  REPEAT 4 [FD :A RT 90] ; the sides\n\n\
+                     END\n\
+                     TO TWO\nSQUARE 10\nSQUARE 20\nEND\nMAKE \"X [1 \"A]\nPPROP \"P \"Q 5";
+      for line in defines.lines().chain(input.lines()) {
+        evaluator.feed(line);
+      }
+      evaluator.printed.take().unwrap()
+    };
+    let square = vec!["TO SQUARE :A ; a comment", "  ; draws it", "  REPEAT 4 [FD :A RT 90] ; the sides",
+                      "", "END"];
+    let two = ["TO TWO", "SQUARE 10", "SQUARE 20", "END"];
+    assert_eq!(square, run("PO \"SQUARE"));
+    assert_eq!(two.iter().chain(square.iter()).collect::<Vec<_>>(),
+               run("PO [TWO SQUARE]").iter().collect::<Vec<_>>());
+    assert_eq!(vec!["TO SQUARE :A ; a comment", "TO TWO"], run("POTS"));
+    assert_eq!(vec!["TO F :X :Y", "END"], run("RUN [TO F :X :Y]\nEND\nPO \"F"));
+    assert_eq!(square.iter().chain(two.iter()).collect::<Vec<_>>(),
+               run("POPS").iter().collect::<Vec<_>>());
+    assert_eq!(square.iter().chain(two.iter()).cloned()
+                     .chain(vec!["MAKE \"X [1 \"A]", "PPROP \"P \"Q 5"]).collect::<Vec<_>>(),
+               run("POALL"));
+    assert_eq!(vec!["MAKE \"X [1 \"A]", "PPROP \"P \"Q 5"], run("PO [[] [X] [P]]"));
+    assert_eq!(vec!["I don't know how to SQUARE in TWO"], run("ERASE \"SQUARE\nTWO"));
+    assert_eq!(vec!["I don't know how to TWO"], run("ER [TWO]\nPO \"TWO"));
+    assert_eq!(vec!["PO doesn't like FD as input"], run("PO \"FD"));
+    // Nothing is erased when one of them can't be.
+    assert_eq!(vec!["I don't know how to NONE", "TO SQUARE :A ; a comment", "TO TWO"],
+               run("ERASE [SQUARE NONE]\nPOTS"));
+    assert_eq!(vec!["MAKE \"X [1 \"A]", "PPROP \"P \"Q 5"], run("ERPS\nPOALL"));
+    assert_eq!(vec!["TO SQUARE :A ; a comment", "TO TWO"],
+               run("ERASE [[] [X] [P]]\nPOTS\nPONS\nPPS"));
+    assert_eq!(Vec::<String>::new(), run("ERALL\nPOALL"));
+    assert_eq!(vec!["TO TWO"], run("ERPLS\nER \"SQUARE\nPOTS\nPPS"));
+    run_test("TO SQUARE :A ; a comment\n  ; draws it\nREPEAT 2 [FD :A RT 90] ; sides\nEND\nSQUARE 10",
+             CON!((0.0, 0.0), (0.0, 10.0), (10.0, 10.0)));
   }
-}
 
-#[allow(dead_code)]
-fn main() {
-  // 1 + (2 * (3 + 4 * -5) + -6 * -(-7 + -8)) * 9
-  let graphics = Box::new(turtle::GraphicsStub::new());
-  let mut evaluator = Evaluator::new(graphics);
-  loop {
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
-    // pratt_parse_debug(input.trim());
-    evaluator.feed(&input);
+  #[test]
+  fn test_save_load() {
+    let file_name = |name: &str| {
+      let file_name = format!("logo-test-{}-{}.lgo", std::process::id(), name);
+      std::env::temp_dir().join(file_name).to_string_lossy().into_owned()
+    };
+    let new_evaluator = |graphics: &turtle::GraphicsStub| {
+      let mut evaluator = Evaluator::new(Box::new(graphics.clone()));
+      evaluator.printed = Some(Vec::new());
+      evaluator
+    };
+    let workspace = "TO SQUARE :A ; a comment\n  REPEAT 4 [FD :A \\\n  RT 90]\nEND\n\
+                     MAKE \"L [1 [\"A -2.5] (B)]\nMAKE \"W \"HI\nMAKE \"A {1 [2]}@0\nMAKE \"N -3\n\
+                     PPROP \"ANA \"SCORE 95\nPPROP \"ANA \"CLASS [3 B]";
+    for (name, text, call) in &[
+      ("ivan", include_str!("../logo-examples/ivan.lgo"), "DIJ 100 7"),
+      ("msw", include_str!("../logo-examples/rjes-msw.lgo"), "cvijet 6 5 20 40"),
+      ("pc", include_str!("../logo-examples/rjes-pc.lgo"), "trokuti 5 50"),
+      ("workspace", workspace, "SQUARE 10"),
+    ] {
+      let graphics = turtle::GraphicsStub::new();
+      let mut evaluator = new_evaluator(&graphics);
+      for line in text.lines().chain(std::iter::once(*call)) {
+        evaluator.feed(line);
+      }
+      let source = evaluator.workspace_source();
+      let file_name = file_name(name);
+      assert_eq!(Ok(()), evaluator.save(&file_name, &source));
+      let loaded_graphics = turtle::GraphicsStub::new();
+      let mut loaded = new_evaluator(&loaded_graphics);
+      assert_eq!(Ok(()), loaded.load(&file_name));
+      loaded.feed(call);
+      std::fs::remove_file(&file_name).unwrap();
+      assert_eq!(source, loaded.workspace_source(), "{}", name);
+      assert_eq!(graphics.invocations.take(), loaded_graphics.invocations.take(), "{}", name);
+      assert_eq!(Some(Vec::new()), loaded.printed);
+    }
+    // SAVEL saves only what PO would print.
+    let graphics = turtle::GraphicsStub::new();
+    let mut evaluator = new_evaluator(&graphics);
+    for line in workspace.lines() {
+      evaluator.feed(line);
+    }
+    let subset = list(&[list(&[word("SQUARE")]), list(&[word("W")])]);
+    let source = evaluator.contents_source("SAVEL", &contents("SAVEL", &subset).unwrap()).unwrap();
+    let file_name = file_name("savel");
+    assert_eq!(Ok(()), evaluator.save(&file_name, &source));
+    let mut loaded = new_evaluator(&graphics);
+    assert_eq!(Ok(()), loaded.load(&file_name));
+    std::fs::remove_file(&file_name).unwrap();
+    let expected = ["TO SQUARE :A ; a comment", "  REPEAT 4 [FD :A \\", "  RT 90]", "END", "MAKE \"W \"HI"];
+    assert_eq!(expected.to_vec(), loaded.workspace_source());
+    // The same with the procedures, the word gets lowercased and .LGO added to it.
+    let name = format!("LOGO.TEST.{}", std::process::id());
+    let saved = format!("logo.test.{}.lgo", std::process::id());
+    for (save, expected) in &[
+      (format!("SAVE \"{}", name), evaluator.workspace_source()),
+      (format!("SAVEL [[SQUARE] [W]] \"{}", name), expected.iter().map(|line| line.to_string()).collect()),
+    ] {
+      evaluator.feed(save);
+      let mut loaded = new_evaluator(&graphics);
+      loaded.feed(&format!("LOAD \"{}", name));
+      std::fs::remove_file(&saved).unwrap();
+      assert_eq!(*expected, loaded.workspace_source(), "{}", save);
+      assert_eq!((Some(Vec::new()), Some(Vec::new())), (evaluator.printed.clone(), loaded.printed));
+    }
+    let prefix = "Can't open file no.such.file.lgo: ";
+    assert_eq!(prefix, &run_error("LOAD \"NO.SUCH.FILE")[.. prefix.len()]);
+  }
+
+  #[test]
+  fn test_frames() {
+    for mode in &[Mode::Compile, Mode::Interpret] {
+      let mut evaluator = Evaluator::new(Box::new(turtle::GraphicsStub::new()));
+      evaluator.set_mode(*mode);
+      evaluator.printed = Some(Vec::new());
+      let stacks = evaluator.stacks();
+      // Every failed line leaves the evaluator the way it was before it.
+      for line in &["TO F :N\nREPEAT 3 [CATCH \"X [IF REPCOUNT = :N [FD [1]]]]\nF :N + 1\nEND", "F 2",
+                    "TO H\nFOR \"I 1 3 [LOCAL \"X CATCH \"ERROR [F 1] BK [2]]\nEND", "H",
+                    "REPEAT 1 [TO G] FD [1]",
+                    "FD 10\\", "]", "CATCH \"X [REPEAT 2 [THROW \"Y]]"] {
+        for line in line.lines() {
+          evaluator.feed(line);
+        }
+        assert_eq!(stacks, evaluator.stacks());
+      }
+      assert!(evaluator.definition.is_none());
+      evaluator.feed("PR 3");
+      let printed = evaluator.printed.take().unwrap();
+      assert_eq!(vec!["FD doesn't like [1] as input in F: REPEAT 3 [CATCH \"X [IF REPCOUNT = :N [FD [1]]]]",
+                      "BK doesn't like [2] as input in H: FOR \"I 1 3 [LOCAL \"X CATCH \"ERROR [F 1] BK [2]]",
+                      "FD doesn't like [1] as input",
+                      "Unexpected ']'",
+                      "Can't find catch tag for Y", "3"],
+                 printed);
+    }
+  }
+
+  #[test]
+  fn test_compare_modes() {
+    for (procedures, calls) in &[
+      (include_str!("../logo-examples/ivan.lgo"), include_str!("../logo-examples/demos/ivan.lgo")),
+      (include_str!("../logo-examples/rjes-msw.lgo"), include_str!("../logo-examples/demos/rjes-msw.lgo")),
+      (include_str!("../logo-examples/rjes-pc.lgo"), include_str!("../logo-examples/demos/rjes-pc.lgo")),
+    ] {
+      let lines: Vec<String> = procedures.lines().chain(calls.lines()).map(String::from).collect();
+      // They run without errors, the same errors in both modes would compare equal.
+      for mode in &[Mode::Compile, Mode::Interpret] {
+        let graphics = turtle::GraphicsStub::new();
+        let mut evaluator = Evaluator::new(Box::new(graphics.clone()));
+        evaluator.set_mode(*mode);
+        evaluator.printed = Some(Vec::new());
+        for line in &lines {
+          evaluator.feed(line);
+        }
+        assert_eq!(0, evaluator.errors, "{:?}", evaluator.printed);
+        assert!(!graphics.invocations.borrow().is_empty());
+      }
+      assert_eq!(Ok(()), compare_modes(&lines));
+      // The procedures alone don't run anything.
+      let lines: Vec<String> = procedures.lines().map(String::from).collect();
+      assert_eq!(Err("Nothing was drawn or printed".to_string()), compare_modes(&lines));
+    }
+    // Errors have to come out the same too.
+    for input in &["TO F\nFD 10 5\nEND\nF", "TO F\nOP FD 10\nEND\nPR F", "PR 1 + [2]",
+                   "REPEAT 2 [3 FD 1]"] {
+      let lines: Vec<String> = input.lines().map(String::from).collect();
+      assert_eq!(Ok(()), compare_modes(&lines));
+    }
+    assert_eq!("FD didn't output to OP in F: OP FD 10",
+               run_error("TO F\nOP FD 10\nEND\nF"));
+    assert_eq!("+ doesn't like [2] as input",
+               run_error("1 + [2]"));
   }
 }
@@ -1,4 +1,5 @@
 use std::collections::HashMap;
+use error::{ErrorKind, LogoError};
 
 #[derive(Debug, Clone, PartialEq)]
 pub enum Token {
@@ -34,6 +35,8 @@ pub enum Token {
   RBracket,
   LBrace,
   RBrace,
+  // Origin of an array, {1 2 3}@0.
+  At,
 
   // Comparison.
   Less,
@@ -43,7 +46,7 @@ pub enum Token {
   Equal,
 }
 
-const CHAR_TO_TOKEN_MAP: [(&str, Token); 20] = [
+const CHAR_TO_TOKEN_MAP: [(&str, Token); 21] = [
   // Two-char tokens and their one-char versions.
   ("\\\n", Token::LineCont),
   ("<=", Token::LessEq),
@@ -65,6 +68,7 @@ const CHAR_TO_TOKEN_MAP: [(&str, Token); 20] = [
   ("]", Token::RBracket),
   ("{", Token::LBrace),
   ("}", Token::RBrace),
+  ("@", Token::At),
   ("=", Token::Equal),
 ];
 
@@ -90,16 +94,12 @@ impl Lexer {
     }
   }
 
-  fn error(&self, info: &str) -> Result<Vec<Token>, String> {
-    Err(format!("Error at pos {} ({}): {}", self.pos, self.input.iter().collect::<String>(), info))
-  }
-
   fn peek(&self) -> Option<char> {
-    self.input.get(self.pos).map(|&c| c)
+    self.input.get(self.pos).copied()
   }
 
   fn peek2(&self) -> Option<char> {
-    self.input.get(self.pos + 1).map(|&c| c)
+    self.input.get(self.pos + 1).copied()
   }
 
   fn end(&self) -> bool {
@@ -114,7 +114,7 @@ impl Lexer {
     self
   }
 
-  // ?_.[a-z][A-Z][0-9]
+  // ?#_.[a-z][A-Z][0-9]
   fn collect_word(&mut self) -> String {
     let mut word = String::new();
     loop {
@@ -133,7 +133,8 @@ impl Lexer {
         Some(c @ '0' ..= '9') |
         Some(c @ '_') |
         Some(c @ '.') |
-        Some(c @ '?') => {
+        Some(c @ '?') |
+        Some(c @ '#') => {
           self.advance();
           word.push(c.to_ascii_uppercase());
         },
@@ -145,7 +146,7 @@ impl Lexer {
     word
   }
 
-  fn process(&mut self) -> Result<Vec<Token>, String> {
+  fn process(&mut self) -> Result<Vec<Token>, Box<LogoError>> {
     // Make sure we end with a newline which gets converted to LineEnd or LineCont later.
     if self.input.last() != Some(&'\n') {
       self.input.push('\n');
@@ -160,6 +161,14 @@ impl Lexer {
         "".to_string()
       };
 
+      // Comments go up to the end of the line.
+      if c1 == ';' {
+        while self.peek().is_some_and(|c| c != '\n') {
+          self.advance();
+        }
+        continue;
+      }
+
       let token;
       if let Some(t) = mapping.get(cc.as_str()) {
         self.advance().advance();
@@ -179,11 +188,10 @@ impl Lexer {
           token = Token::Num(num);
         } else if let Ok(num) = word.parse::<f32>() {
           token = Token::Float(num);
-        } else if word.len() > 0 {
+        } else if !word.is_empty() {
           token = Token::Function(word);
-        } else { // word.len() == 0
-          let f = &format!("unknown char {}", c);
-          return self.error(f);
+        } else { // word.is_empty()
+          return Err(ErrorKind::DontKnowHow(c).into());
         }
       }
 
@@ -192,15 +200,29 @@ impl Lexer {
       }
     }
 
-    let tokens = std::mem::replace(&mut self.tokens, Vec::new());
+    let tokens = std::mem::take(&mut self.tokens);
     Ok(tokens)
   }
 }
 
-pub fn process(input: &str) -> Result<Vec<Token>, String> {
+pub fn process(input: &str) -> Result<Vec<Token>, Box<LogoError>> {
   Lexer::new(input).process()
 }
 
+// Source text of a token, for turning the words of a list back into code.
+pub fn token_text(token: &Token) -> String {
+  match token {
+    Token::Function(name) => { name.clone() },
+    Token::Var(var) => { format!(":{}", var) },
+    Token::Word(word) => { format!("\"{}", word) },
+    Token::Num(num) => { format!("{}", num) },
+    Token::Float(num) => { format!("{}", num) },
+    _ => {
+      CHAR_TO_TOKEN_MAP.iter().find(|(_, t)| t == token).map_or(String::new(), |(text, _)| text.to_string())
+    },
+  }
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -212,15 +234,26 @@ mod tests {
   }
 
   fn test_err(input: &str, expected: &str) {
-    let lexed = Lexer::new(input).process();
+    let lexed = Lexer::new(input).process().map_err(|err| err.to_string());
     let expected = Err(expected.to_string());
     assert_eq!(expected, lexed, "'''{}'''", input);
   }
 
   #[test]
   fn unknown_char() {
-    test_err("fd 20`~\n",
-             "Error at pos 5 (fd 20`~\n): unknown char `");
+    test_err("fd 20`~\n", "I don't know how to `");
+  }
+
+  #[test]
+  fn comment() {
+    test_ok("fd 1 ; go [up\n; rt\n", &[
+      Token::Function("FD".to_string()),
+      Token::Whitespace,
+      Token::Num(1),
+      Token::Whitespace,
+      Token::LineEnd,
+      Token::LineEnd,
+    ]);
   }
 
   #[test]
@@ -294,6 +327,20 @@ mod tests {
     ]);
   }
 
+  #[test]
+  fn array() {
+    test_ok("{1 a}@0\n", &[
+      Token::LBrace,
+      Token::Num(1),
+      Token::Whitespace,
+      Token::Function("A".to_string()),
+      Token::RBrace,
+      Token::At,
+      Token::Num(0),
+      Token::LineEnd,
+    ]);
+  }
+
   #[test]
   fn line_cont() {
     test_ok("REPEAT 4 [FD 40\\\nRT 90]fd 50\n", &[
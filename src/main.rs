@@ -1,9 +1,11 @@
 extern crate ggez;
-#[macro_use(defer)]
 extern crate scopeguard;
 
+mod error;
 mod lexer;
 mod parser;
+mod value;
+mod compiler;
 mod turtle;
 mod evaluator;
 
@@ -40,35 +42,55 @@ fn get_input_receiver() -> std::sync::mpsc::Receiver<String> {
   receiver
 }
 
+// --max-depth N, how deep procedure calls and run lists can go before "Stack overflow".  The
+// evaluator's thread gets the stack for it, a depth which needs too much of it is an error.
+fn max_depth(args: &[String]) -> Result<usize, String> {
+  let arg = match args.iter().position(|arg| arg == "--max-depth") {
+    Some(i) => { args.get(i + 1).ok_or("--max-depth needs a number")? },
+    None => { return Ok(evaluator::DEFAULT_MAX_DEPTH); },
+  };
+  match arg.parse() {
+    Ok(max_depth) if evaluator::stack_size(max_depth).is_some() => { Ok(max_depth) },
+    Ok(_) => { Err(format!("--max-depth {} is too deep to run", arg)) },
+    Err(_) => { Err(format!("--max-depth {} is not a number", arg)) },
+  }
+}
+
 struct MainState {
   screen: ScreenImage,
-  receiver: std::sync::mpsc::Receiver<String>,
-  evaluator: evaluator::Evaluator,
-  graphics: turtle::GraphicsStub,
+  // What the evaluator draws, it runs the input on its own thread.
+  commands: std::sync::mpsc::Receiver<turtle::Command>,
 }
 
 impl MainState {
-  fn new(ctx: &Context) -> GameResult<Self> {
-    let graphics = turtle::GraphicsStub::new();
+  fn new(ctx: &Context, max_depth: usize) -> GameResult<Self> {
+    let interpret = std::env::args().any(|arg| arg == "--interpret");
+    let receiver = get_input_receiver();
+    let (sender, commands) = std::sync::mpsc::channel();
+    evaluator::spawn(max_depth, move || {
+      let mut evaluator = evaluator::Evaluator::new(Box::new(turtle::GraphicsSender::new(sender)));
+      if interpret {
+        evaluator.set_mode(evaluator::Mode::Interpret);
+      }
+      evaluator.set_max_depth(max_depth);
+      for input in receiver {
+        evaluator.feed(&input);
+      }
+    })?;
 
     Ok(Self {
       screen: ScreenImage::new(ctx, ImageFormat::Rgba8UnormSrgb, 1., 1., 1),
-      receiver: get_input_receiver(),
-      evaluator: evaluator::Evaluator::new(Box::new(graphics.clone())),
-      graphics,
+      commands,
     })
   }
 }
 
 impl event::EventHandler<GameError> for MainState {
   fn update(&mut self, _ctx: &mut Context) -> GameResult {
-    if let Ok(input) = self.receiver.try_recv() {
-      self.evaluator.feed(&input);
-    }
     Ok(())
   }
   fn draw(&mut self, ctx: &mut Context) -> GameResult {
-    let invocations = self.graphics.invocations.replace(Vec::new());
+    let invocations: Vec<turtle::Command> = self.commands.try_iter().collect();
     let mut canvas = Canvas::from_screen_image(ctx, &mut self.screen, None);
     for cmd in invocations {
       match cmd {
@@ -94,11 +116,71 @@ impl event::EventHandler<GameError> for MainState {
   }
 }
 
+// Runs the files one after the other, the procedures and then the instructions which use them,
+// in the compiled and in the interpreted mode without opening the window.  Checks that they draw
+// and print the same, and that they draw or print something at all.
+fn compare_files(files: &[String]) -> i32 {
+  let mut lines = Vec::new();
+  for file in files {
+    match std::fs::read_to_string(file) {
+      Ok(text) => { lines.extend(text.lines().map(String::from)); },
+      Err(err) => {
+        println!("{}: {}", file, err);
+        return 1;
+      },
+    }
+  }
+  match evaluator::compare_modes(&lines) {
+    Ok(()) => {
+      println!("{}: same in both modes", files.join(" "));
+      0
+    },
+    Err(err) => {
+      println!("{}: {}", files.join(" "), err);
+      1
+    },
+  }
+}
+
+// Times each file in both modes, the best of a few runs.  Like --compare, nothing gets drawn.
+fn bench_files(files: &[String]) -> i32 {
+  const RUNS: usize = 3;
+  for file in files {
+    let lines: Vec<String> = match std::fs::read_to_string(file) {
+      Ok(text) => { text.lines().map(String::from).collect() },
+      Err(err) => {
+        println!("{}: {}", file, err);
+        return 1;
+      },
+    };
+    let times: Vec<_> = (0 .. RUNS).map(|_| evaluator::time_modes(&lines)).collect();
+    let interpreted = times.iter().map(|time| time.0).min().unwrap();
+    let compiled = times.iter().map(|time| time.1).min().unwrap();
+    println!("{}: interpreted {} ms, compiled {} ms, {:.1}x", file, interpreted.as_millis(),
+             compiled.as_millis(), interpreted.as_secs_f64() / compiled.as_secs_f64());
+  }
+  0
+}
+
 fn main() -> GameResult {
+  let args: Vec<String> = std::env::args().collect();
+  if args.len() > 1 && (args[1] == "--compare" || args[1] == "--bench") {
+    let files = args[2..].to_vec();
+    let run = if args[1] == "--compare" { compare_files } else { bench_files };
+    let ran = evaluator::spawn(evaluator::DEFAULT_MAX_DEPTH, move || run(&files));
+    std::process::exit(ran.map_or(1, |thread| thread.join().unwrap_or(1)));
+  }
+  let max_depth = match max_depth(&args) {
+    Ok(max_depth) => { max_depth },
+    Err(err) => {
+      println!("{}", err);
+      std::process::exit(1);
+    },
+  };
   let cb = ContextBuilder::new("Logo", "ggez")
       .window_setup(WindowSetup::default().title("PC Logo 4.0"))
       .window_mode(WindowMode::default().dimensions(WIDTH, HEIGHT));
   let (ctx, event_loop) = cb.build()?;
-  let state = MainState::new(&ctx)?;
+  let state = MainState::new(&ctx, max_depth)?;
   event::run(ctx, event_loop, state)
 }
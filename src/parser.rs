@@ -3,25 +3,101 @@
 
 use lexer;
 use lexer::Token;
+use error::{ErrorKind, LogoError};
+use std::rc::Rc;
+use value::{Array, List, Value};
 
 pub type TokenList = std::collections::VecDeque<Token>;
 pub type ListType = std::collections::VecDeque<AST>;
 pub type WordType = String;
 pub type NumType = f32;
 
+// Number of inputs a procedure takes: the default one, and the min/max it accepts when called
+// with parens, eg. (LIST 1 2 3).  No max means it takes any number of inputs.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Arity {
+  pub default: usize,
+  pub min: usize,
+  pub max: Option<usize>,
+}
+
+impl Arity {
+  pub fn new(default: usize, min: usize, max: Option<usize>) -> Self {
+    Arity { default, min, max }
+  }
+
+  pub fn fixed(num: usize) -> Self {
+    Arity::new(num, num, Some(num))
+  }
+}
+
+pub type Arities = std::collections::HashMap<WordType, Arity>;
+
+// Arithmetic and comparison operators.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Operator {
+  Plus,
+  Minus,
+  Multiply,
+  Divide,
+  Modulo,
+  Less,
+  LessEq,
+  Greater,
+  GreaterEq,
+  Equal,
+}
+
+fn operator(token: &Option<Token>) -> Operator {
+  match token {
+    Some(Token::Plus) => { Operator::Plus },
+    Some(Token::Minus) => { Operator::Minus },
+    Some(Token::Multiply) => { Operator::Multiply },
+    Some(Token::Divide) => { Operator::Divide },
+    Some(Token::Modulo) => { Operator::Modulo },
+    Some(Token::Less) => { Operator::Less },
+    Some(Token::LessEq) => { Operator::LessEq },
+    Some(Token::Greater) => { Operator::Greater },
+    Some(Token::GreaterEq) => { Operator::GreaterEq },
+    Some(Token::Equal) => { Operator::Equal },
+    _ => { panic!("Invalid token for operator {:?}", token) }
+  }
+}
+
+// The operator as it's written, for the error messages.
+impl std::fmt::Display for Operator {
+  fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+    let text = match self {
+      Operator::Plus => { "+" },
+      Operator::Minus => { "-" },
+      Operator::Multiply => { "*" },
+      Operator::Divide => { "/" },
+      Operator::Modulo => { "%" },
+      Operator::Less => { "<" },
+      Operator::LessEq => { "<=" },
+      Operator::Greater => { ">" },
+      Operator::GreaterEq => { ">=" },
+      Operator::Equal => { "=" },
+    };
+    write!(f, "{}", text)
+  }
+}
+
 // NumExpr, TODO: Remove Clone?
 #[derive(Debug, Clone, PartialEq)]
+#[allow(clippy::upper_case_acronyms)]
 pub enum AST {
   Negation(Box<AST>),  // The only unary operator is negation.
-  Binary(Token, Box<AST>, Box<AST>),  // Arithmetic  operators.
-  Comparison(Token, Box<AST>, Box<AST>),  // Comparison operators.
-  Nary(Token, ListType),  // + and * can take all args, eg. (+ 1 2 3 4) evaluates to 10.
+  Binary(Operator, Box<AST>, Box<AST>),  // Arithmetic  operators.
+  Comparison(Operator, Box<AST>, Box<AST>),  // Comparison operators.
+  Nary(Operator, ListType),  // + and * can take all args, eg. (+ 1 2 3 4) evaluates to 10.
   Num(NumType),  // Numbers.  Currently only floats, maybe some day also ints.
-  Function(WordType),  // name
-  FunctionReturn(Box<AST>),  // return value from function
+  Function(WordType),  // name, only as the procedure name in TO FOO :A
+  Call(WordType, ListType),  // FD 50, (LIST 1 2 3), a procedure call with its inputs
   Var(WordType),  // :ASD
   Word(WordType),  // "BIRD
-  List(ListType), // [1 2 MAKE "A "BSD]
+  List(List), // [1 2 MAKE "A "BSD], data (words, numbers and lists), parsed only when run
+  Array(List, i32),  // {1 2 [3]}@0, the items are data too, a new array every time it's evaluated
   Parens(ListType),  // (1 2 + 3)
   ExprLine(ListType),  // Line of ASTs
   // Parser returns None in case it doesn't have a fully parsed expression.  Ie. a function
@@ -64,17 +140,250 @@ fn is_comparison(token: &Option<Token>) -> bool {
   }
 }
 
-fn capture_list(queue: &mut TokenList, last_token: &Option<Token>) -> Result<ListType, String> {
+fn is_operator(token: Option<&Token>) -> bool {
+  matches!(token,
+           Some(Token::Plus) | Some(Token::Minus) | Some(Token::Multiply) | Some(Token::Divide) |
+           Some(Token::Modulo) | Some(Token::Less) | Some(Token::LessEq) | Some(Token::Greater) |
+           Some(Token::GreaterEq) | Some(Token::Equal))
+}
+
+fn skip_whitespace(queue: &mut TokenList) {
+  while queue.front() == Some(&Token::Whitespace) {
+    queue.pop_front();
+  }
+}
+
+// No more inputs for the current call: end of line, or a closing paren or bracket.
+fn at_end(queue: &TokenList) -> bool {
+  matches!(queue.iter().find(|&token| token != &Token::Whitespace),
+           None | Some(Token::LineEnd) | Some(Token::RParen) | Some(Token::RBracket))
+}
+
+// Next thing is an infix operator (and not a negative number, like the -5 in (LIST 1 -5)).
+fn at_infix(queue: &TokenList) -> bool {
+  if queue.front() == Some(&Token::Whitespace) {
+    is_operator(queue.get(1)) &&
+        !(queue.get(1) == Some(&Token::Minus) && queue.get(2) != Some(&Token::Whitespace))
+  } else {
+    is_operator(queue.front())
+  }
+}
+
+fn next_is(queue: &TokenList, name: &str) -> bool {
+  match queue.iter().find(|&token| token != &Token::Whitespace) {
+    Some(Token::Function(function)) => { function == name },
+    _ => { false },
+  }
+}
+
+// A token where it doesn't belong, closing ones are missing their opening one.
+fn unexpected(token: &Token) -> Box<LogoError> {
+  match token {
+    Token::RParen => { ErrorKind::Unexpected(')').into() },
+    Token::RBracket => { ErrorKind::Unexpected(']').into() },
+    Token::RBrace => { ErrorKind::Unexpected('}').into() },
+    token => { ErrorKind::DontKnowHow(lexer::token_text(token)).into() },
+  }
+}
+
+fn capture_list(arities: &Arities, queue: &mut TokenList, last_token: &Option<Token>) -> Result<ListType, Box<LogoError>> {
   let mut list = ListType::new();
-  while queue.len() > 0 && queue.front() != Some(&Token::LineEnd) &&
-                            queue.front() != Some(&Token::RParen) &&
-                            queue.front() != Some(&Token::RBracket) {
-    list.push_back(parse_one(queue, last_token)?);
+  while !at_end(queue) {
+    list.push_back(parse_one(arities, queue, last_token)?);
   }
+  skip_whitespace(queue);
   Ok(list)
 }
 
-fn parse_left(queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST, String> {
+// Words in a list are the tokens between spaces, [FD :A*2] is the word FD and the word :A*2.
+fn data_word(tokens: &[Token]) -> Value {
+  match tokens {
+    [Token::Num(num)] => { Value::Num(*num as NumType) },
+    [Token::Float(num)] => { Value::Num(*num) },
+    [Token::Minus, Token::Num(num)] => { Value::Num(-*num as NumType) },
+    [Token::Minus, Token::Float(num)] => { Value::Num(-*num) },
+    _ => { Value::Word(tokens.iter().map(lexer::token_text).collect()) },
+  }
+}
+
+// Lists are data (words, numbers and sublists), they only get parsed as code when they're run,
+// by which time all the procedures they call are known.
+fn capture_data_list(queue: &mut TokenList) -> Result<List, Box<LogoError>> {
+  Ok(List::from(capture_data(queue, Token::RBracket)?))
+}
+
+// {1 2 [3]}@0, the items are data like the ones of a list.  Gives them and the origin.
+fn capture_data_array(queue: &mut TokenList) -> Result<(Vec<Value>, i32), Box<LogoError>> {
+  let items = capture_data(queue, Token::RBrace)?;
+  if queue.front() != Some(&Token::At) {
+    return Ok((items, 1));
+  }
+  queue.pop_front();
+  let sign = if queue.front() == Some(&Token::Minus) { queue.pop_front(); -1 } else { 1 };
+  match queue.pop_front() {
+    Some(Token::Num(origin)) => { Ok((items, sign * origin)) },
+    None | Some(Token::LineEnd) | Some(Token::Whitespace) => {
+      Err(ErrorKind::NotEnoughInputs("@".to_string()).into())
+    },
+    Some(token) => {
+      Err(ErrorKind::DoesntLike("@".to_string(), Value::Word(lexer::token_text(&token))).into())
+    },
+  }
+}
+
+// The items up to the closing bracket or brace.
+fn capture_data(queue: &mut TokenList, close: Token) -> Result<Vec<Value>, Box<LogoError>> {
+  let mut list = Vec::new();
+  let mut word = Vec::new();
+  loop {
+    let token = queue.pop_front();
+    match token {
+      None | Some(Token::LineEnd) | Some(Token::LineCont) | Some(Token::Whitespace) |
+      Some(Token::LBracket) | Some(Token::RBracket) | Some(Token::LBrace) | Some(Token::RBrace) |
+      Some(Token::LParen) | Some(Token::RParen) => {},
+      Some(token) => {
+        word.push(token);
+        continue;
+      },
+    }
+    if !word.is_empty() {
+      list.push(data_word(&word));
+      word.clear();
+    }
+    match token {
+      Some(Token::LBracket) => {
+        list.push(Value::List(capture_data_list(queue)?));
+      },
+      Some(Token::LBrace) => {
+        let (items, origin) = capture_data_array(queue)?;
+        list.push(Value::Array(Array::new(items, origin)));
+      },
+      Some(ref end) if *end == close => {
+        return Ok(list);
+      },
+      Some(end @ Token::RBracket) | Some(end @ Token::RBrace) => {
+        return Err(unexpected(&end));
+      },
+      // Parens are always words of their own, [(LIST 1 2)] has 4 items.
+      Some(paren @ Token::LParen) | Some(paren @ Token::RParen) => {
+        list.push(Value::Word(lexer::token_text(&paren)));
+      },
+      None | Some(Token::LineEnd) => {
+        return Err(ErrorKind::NotFound(if close == Token::RBrace { '}' } else { ']' }).into());
+      },
+      _ => {},
+    }
+  }
+}
+
+// TO FOO :A :B, the procedure isn't known yet so its name and inputs are taken as they are.
+fn parse_to(queue: &mut TokenList) -> Result<AST, Box<LogoError>> {
+  let mut args = ListType::new();
+  while !at_end(queue) {
+    skip_whitespace(queue);
+    args.push_back(match queue.pop_front() {
+      Some(Token::Function(name)) => { AST::Function(name) },
+      Some(Token::Var(var)) => { AST::Var(var) },
+      Some(Token::Word(word)) => { AST::Word(word) },
+      Some(Token::Num(num)) => { AST::Num(num as NumType) },
+      Some(Token::Float(num)) => { AST::Num(num) },
+      token => {
+        let text = token.as_ref().map_or(String::new(), lexer::token_text);
+        return Err(ErrorKind::DoesntLike("TO".to_string(), Value::Word(text)).into());
+      }
+    });
+  }
+  Ok(AST::Call("TO".to_string(), args))
+}
+
+// Branch of PC Logo's IF cond THEN instr ELSE instr, a list or the bare instructions up to the
+// ELSE (or the end of the line), which become a list as well.  An ELSE goes with the nearest IF,
+// the ones of the IFs inside the branch stay in it.
+fn parse_branch(arities: &Arities, queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST, Box<LogoError>> {
+  skip_whitespace(queue);
+  if queue.front() == Some(&Token::LBracket) {
+    return parse_one(arities, queue, last_token);
+  }
+  let mut branch = TokenList::new();
+  let mut depth = 0;
+  let mut ifs = 0;
+  loop {
+    match queue.front() {
+      None | Some(Token::LineEnd) => { break; },
+      Some(Token::Function(name)) if name == "IF" && depth == 0 => { ifs += 1; },
+      Some(Token::Function(name)) if name == "ELSE" && depth == 0 && ifs > 0 => { ifs -= 1; },
+      Some(Token::Function(name)) if name == "ELSE" && depth == 0 => { break; },
+      Some(Token::RParen) | Some(Token::RBracket) if depth == 0 => { break; },
+      Some(Token::LParen) | Some(Token::LBracket) => { depth += 1; },
+      Some(Token::RParen) | Some(Token::RBracket) => { depth -= 1; },
+      _ => {},
+    }
+    branch.push_back(queue.pop_front().unwrap());
+  }
+  branch.push_back(Token::RBracket);
+  let list = capture_data_list(&mut branch)?;
+  if list.is_empty() {
+    return Err(ErrorKind::NotEnoughInputs("IF".to_string()).into());
+  }
+  Ok(AST::List(list))
+}
+
+fn parse_if(arities: &Arities, queue: &mut TokenList, last_token: &Option<Token>) -> Result<ListType, Box<LogoError>> {
+  let mut args = ListType::new();
+  if at_end(queue) {
+    return Ok(args);
+  }
+  args.push_back(parse_one(arities, queue, last_token)?);
+  if next_is(queue, "THEN") {
+    skip_whitespace(queue);
+    queue.pop_front();
+    args.push_back(parse_branch(arities, queue, last_token)?);
+  } else if at_end(queue) {
+    return Ok(args);
+  } else {
+    args.push_back(parse_one(arities, queue, last_token)?);
+    // IF cond [list] [list] is an IFELSE, like in PC Logo.
+    let next = queue.iter().find(|&token| token != &Token::Whitespace);
+    if matches!(args.back(), Some(AST::List(_))) && next == Some(&Token::LBracket) {
+      args.push_back(parse_one(arities, queue, last_token)?);
+    }
+  }
+  if next_is(queue, "ELSE") {
+    skip_whitespace(queue);
+    queue.pop_front();
+    args.push_back(parse_branch(arities, queue, last_token)?);
+  }
+  Ok(args)
+}
+
+// A call takes the default number of inputs, or when it's first inside parens all of the inputs
+// up to the closing paren, (LIST 1 2 3).
+fn parse_call(arities: &Arities, queue: &mut TokenList, name: WordType, parens: bool) -> Result<AST, Box<LogoError>> {
+  let arity = match arities.get(&name) {
+    Some(arity) => { *arity },
+    None => { return Err(ErrorKind::DontKnowHow(name).into()); }
+  };
+  let last_token = Some(Token::Function(name.clone()));
+  let mut args = ListType::new();
+  if name == "IF" {
+    args = parse_if(arities, queue, &last_token)?;
+  }
+  while if parens { !at_end(queue) && !at_infix(queue) } else { args.len() < arity.default } {
+    if at_end(queue) {
+      return Err(ErrorKind::NotEnoughInputs(name).into());
+    }
+    args.push_back(parse_one(arities, queue, &last_token)?);
+  }
+  if args.len() < arity.min {
+    return Err(ErrorKind::NotEnoughInputs(name).into());
+  }
+  if arity.max.is_some_and(|max| args.len() > max) {
+    return Err(ErrorKind::TooManyInputs(name).into());
+  }
+  Ok(AST::Call(name, args))
+}
+
+fn parse_left(arities: &Arities, queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST, Box<LogoError>> {
   let left;
   if queue.front() == Some(&Token::Whitespace) {
     queue.pop_front();
@@ -88,7 +397,11 @@ fn parse_left(queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST,
       left = AST::Num(f);
     },
     Some(Token::Function(name)) => {
-      left = AST::Function(name);
+      if name == "TO" {
+        left = parse_to(queue)?;
+      } else {
+        left = parse_call(arities, queue, name, false)?;
+      }
     },
     Some(Token::Var(var)) => {
       left = AST::Var(var);
@@ -97,30 +410,55 @@ fn parse_left(queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST,
       left = AST::Word(word);
     },
     Some(Token::LParen) => {
-      let expr_list = capture_list(queue, &token)?;
+      skip_whitespace(queue);
+      match queue.front().cloned() {
+        Some(Token::Function(name)) if name != "TO" => {
+          queue.pop_front();
+          let call = parse_call(arities, queue, name, true)?;
+          left = parse_infix(arities, queue, call, &token)?;
+          skip_whitespace(queue);
+        },
+        _ => {
+          let expr_list = capture_list(arities, queue, &token)?;
+          if expr_list.len() > 1 {
+            return Err(ErrorKind::TooMuchInsideParens.into());
+          }
+          left = AST::Parens(expr_list);
+        },
+      }
       // RParen should be next, which is consumed by this LParen.
-      if queue.pop_front() != Some(Token::RParen) {
-        return Err(format!("unmatched left paren operand {:?} last_token {:?}", expr_list, last_token));
+      match queue.pop_front() {
+        Some(Token::RParen) => {},
+        None | Some(Token::LineEnd) => {
+          return Err(ErrorKind::NotFound(')').into());
+        },
+        Some(_) => {
+          return Err(ErrorKind::TooMuchInsideParens.into());
+        },
       }
-      left = AST::Parens(expr_list);
     },
     Some(Token::LBracket) => {
-      let list = capture_list(queue, &token)?;
-      // RBracket is next, and it's consumed by this LBracket.
-      if queue.pop_front() != Some(Token::RBracket) {
-        return Err(format!("unmatched left bracket list {:?} last_token {:?}", list, last_token));
-      }
-      left = AST::List(list);
+      // RBracket is consumed by the list.
+      left = AST::List(capture_data_list(queue)?);
+    },
+    Some(Token::LBrace) => {
+      // Same with RBrace and the origin.
+      let (items, origin) = capture_data_array(queue)?;
+      left = AST::Array(List::from(items), origin);
     },
     Some(Token::Minus) if queue.front() != Some(&Token::Whitespace) => {
       match queue.front() {
-        Some(&Token::Num(_)) | Some(&Token::LParen) => {
-          let operand = parse_left(queue, &Some(Token::Negation))?;
+        Some(&Token::Num(_)) | Some(&Token::Float(_)) | Some(&Token::LParen) => {
+          let operand = parse_left(arities, queue, &Some(Token::Negation))?;
           left = AST::Negation(Box::new(operand));
         },
-        _ => {
-          return Err(format!("bad token after Minus queue {:?}", queue));
-        }
+        None | Some(&Token::LineEnd) => {
+          return Err(ErrorKind::NotEnoughInputs("-".to_string()).into());
+        },
+        Some(token) => {
+          let text = lexer::token_text(token);
+          return Err(ErrorKind::DoesntLike("-".to_string(), Value::Word(text)).into());
+        },
       }
     },
     // Prefix-style arithmetic or comparison operators.
@@ -129,29 +467,49 @@ fn parse_left(queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST,
     Some(Token::GreaterEq) | Some(Token::Equal) => {
       if last_token == &Some(Token::LParen) &&
          (token == Some(Token::Plus) || token == Some(Token::Multiply)) {
-        let expr_list = capture_list(queue, &Some(Token::Prefix))?;
-        left = AST::Nary(token.unwrap(), expr_list);
+        let expr_list = capture_list(arities, queue, &Some(Token::Prefix))?;
+        left = AST::Nary(operator(&token), expr_list);
       } else {
-        let l = parse_one(queue, &Some(Token::Prefix))?;
-        let r = parse_one(queue, &Some(Token::Prefix))?;
+        let name = token.as_ref().map_or(String::new(), lexer::token_text);
+        let mut operand = || {
+          if at_end(queue) {
+            return Err(ErrorKind::NotEnoughInputs(name.clone()).into());
+          }
+          parse_one(arities, queue, &Some(Token::Prefix))
+        };
+        let l = operand()?;
+        let r = operand()?;
         if is_comparison(&token) {
-          left = AST::Comparison(token.unwrap(), Box::new(l), Box::new(r));
+          left = AST::Comparison(operator(&token), Box::new(l), Box::new(r));
         } else {
-          left = AST::Binary(token.unwrap(), Box::new(l), Box::new(r));
+          left = AST::Binary(operator(&token), Box::new(l), Box::new(r));
         }
       }
     },
-    _ => {
-      return Err(format!("missing operand or not an operand {:?} last_token {:?} queue {:?}", token, last_token, queue));
+    // Nothing after an operator, or a token which doesn't start an operand.
+    None | Some(Token::LineEnd) => {
+      let name = match last_token {
+        Some(Token::Function(name)) => { name.clone() },
+        Some(token) => { lexer::token_text(token) },
+        None => { String::new() },
+      };
+      return Err(ErrorKind::NotEnoughInputs(name).into());
+    },
+    Some(token) => {
+      return Err(unexpected(&token));
     }
   }
 
-  return Ok(left);
+  Ok(left)
 }
 
-fn parse_one(queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST, String> {
-  let mut left = parse_left(queue, last_token)?;
+fn parse_one(arities: &Arities, queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST, Box<LogoError>> {
+  let left = parse_left(arities, queue, last_token)?;
+  parse_infix(arities, queue, left, last_token)
+}
 
+// Infix operators following the left operand.
+fn parse_infix(arities: &Arities, queue: &mut TokenList, mut left: AST, last_token: &Option<Token>) -> Result<AST, Box<LogoError>> {
   loop {
     // Lookahead for unary minus / negation.
     if queue.len() >= 3 && queue[0] == Token::Whitespace &&
@@ -166,14 +524,14 @@ fn parse_one(queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST, S
       // Left only tokens or end - propagate left to parents right.
       None | Some(Token::LineEnd) | Some(Token::Num(_)) | Some(Token::Float(_)) |
       Some(Token::Function(_)) | Some(Token::Var(_)) | Some(Token::Word(_)) |
-      Some(Token::LParen) | Some(Token::LBracket) => {
+      Some(Token::LParen) | Some(Token::LBracket) | Some(Token::LBrace) => {
         break;
       },
       Some(e @ Token::RParen) |
       Some(e @ Token::RBracket) => {
         // RParen/RBracket propagates back until the last left one which consumes it.
         if last_token.is_none() {
-          return Err(format!("unmatched right bracket {:?} queue {:?} last_token {:?}", e, queue, last_token));
+          return Err(unexpected(e));
         }
         break;
       },
@@ -184,8 +542,8 @@ fn parse_one(queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST, S
         // Needs parsing, handled just below this match (because otherwise we would have double
         // reference to queue).  TODO: Is that true?  Could that code from below be put here?
       },
-      _ => {
-        return Err(format!("Unknown token {:?}", queue.front()));
+      Some(token) => {
+        return Err(unexpected(token));
       },
     }
 
@@ -197,20 +555,57 @@ fn parse_one(queue: &mut TokenList, last_token: &Option<Token>) -> Result<AST, S
       break;
     }
     queue.pop_front();
-    let right = parse_one(queue, &token)?;
+    let right = parse_one(arities, queue, &token)?;
     if is_comparison(&token) {
-      left = AST::Comparison(token.unwrap(), Box::new(left), Box::new(right));
+      left = AST::Comparison(operator(&token), Box::new(left), Box::new(right));
     } else {
-      left = AST::Binary(token.unwrap(), Box::new(left), Box::new(right));
+      left = AST::Binary(operator(&token), Box::new(left), Box::new(right));
     }
   }
 
-  return Ok(left);
+  Ok(left)
+}
+
+// Source text of a list, for running it as code.
+fn list_source(list: &List) -> String {
+  let items: Vec<String> = list.iter().map(|item| {
+    match item {
+      Value::List(list) => { format!("[{}]", list_source(list)) },
+      Value::Word(word) => { word.clone() },
+      Value::Num(num) => { format!("{}", num) },
+      other => { other.to_string() },
+    }
+  }).collect();
+  items.join(" ")
+}
+
+// The text of a line of a procedure body as parse_lines sees it, the lines continued with \ are
+// joined with the next one.
+pub fn source_line(lines: &[String], index: usize) -> Option<String> {
+  let mut parsed = 0;
+  let mut text = Vec::new();
+  for line in lines {
+    if lexer::process(line).is_ok_and(|tokens| tokens.last() == Some(&Token::LineCont)) {
+      text.push(line.trim().trim_end_matches('\\').trim_end());
+      continue;
+    }
+    text.push(line.trim());
+    if parsed == index {
+      return Some(text.join(" "));
+    }
+    parsed += 1;
+    text.clear();
+  }
+  None
 }
 
 #[derive(Default)]
 pub struct Parser {
   saved_tokens: Vec<Token>,
+  // Number of inputs of all the known procedures, needed to tell where a call ends.
+  arities: Arities,
+  // Changes with every arity change, code parsed with an older version needs to be parsed again.
+  version: usize,
 }
 
 impl Parser {
@@ -220,34 +615,80 @@ impl Parser {
     }
   }
 
-  // Should take in only one line (for now).
-  pub fn parse(&mut self, input: &str) -> Result<AST, String> {
+  pub fn set_arity(&mut self, name: &str, arity: Arity) {
+    self.arities.insert(name.to_string(), arity);
+    self.version += 1;
+  }
+
+  // The procedure got erased, calls to it don't parse any more.
+  pub fn remove_arity(&mut self, name: &str) {
+    self.arities.remove(name);
+    self.version += 1;
+  }
+
+  // A line of input, when there are more they run one after the other.
+  pub fn parse(&mut self, input: &str) -> Result<AST, Box<LogoError>> {
+    // The lines before it are dropped too when this one fails.
+    let mut saved_tokens = std::mem::take(&mut self.saved_tokens);
     let mut tokens = lexer::process(input)?;
 
     // In case we have a LineCont save, or load saved tokens.
     if tokens.last() == Some(&Token::LineCont) {
       tokens.pop();
-      self.saved_tokens.append(&mut tokens);
+      // The line break still separates the words.
+      tokens.push(Token::Whitespace);
+      saved_tokens.append(&mut tokens);
+      self.saved_tokens = saved_tokens;
       return Ok(AST::None);
     }
-    if !self.saved_tokens.is_empty() {
-      self.saved_tokens.append(&mut tokens);
-      tokens = std::mem::replace(&mut self.saved_tokens, Vec::new());
+    if !saved_tokens.is_empty() {
+      saved_tokens.append(&mut tokens);
+      tokens = saved_tokens;
     }
 
-    let mut tokens: TokenList = tokens.into_iter().collect();
-    let mut expr_list = ListType::new();
-    while tokens.front().is_some() &&
-          tokens.front() != Some(&Token::LineEnd) {
-      expr_list.push_back(parse_one(&mut tokens, &None)?);
+    Ok(AST::ExprLine(self.parse_tokens(tokens)?))
+  }
+
+  // Lines of a procedure body, a line ending with \ continues in the next one.
+  pub fn parse_lines(&self, lines: &[String]) -> Result<ListType, Box<LogoError>> {
+    let mut parsed = ListType::new();
+    let mut tokens = Vec::new();
+    for line in lines {
+      tokens.append(&mut lexer::process(line)?);
+      if tokens.last() == Some(&Token::LineCont) {
+        tokens.pop();
+        tokens.push(Token::Whitespace);
+        continue;
+      }
+      parsed.push_back(AST::ExprLine(self.parse_tokens(std::mem::take(&mut tokens))?));
     }
-    if tokens.front() == Some(&Token::LineEnd) {
-      tokens.pop_front();
+    Ok(parsed)
+  }
+
+  // Lists which get run (REPEAT 4 [FD 10 RT 90]) are parsed into the expressions to evaluate.
+  // The result is kept with the list, running it again doesn't parse it again.
+  pub fn parse_list(&self, list: &List) -> Result<Rc<ListType>, Box<LogoError>> {
+    if let Some(code) = list.code(self.version) {
+      return Ok(code);
     }
-    if !tokens.is_empty() {
-      return Err(format!("parse should get only one line of input!"));
+    let code = Rc::new(self.parse_tokens(lexer::process(&list_source(list))?)?);
+    list.set_code(self.version, code.clone());
+    Ok(code)
+  }
+
+  fn parse_tokens(&self, tokens: Vec<Token>) -> Result<ListType, Box<LogoError>> {
+    let mut tokens: TokenList = tokens.into_iter().collect();
+    let mut expr_list = ListType::new();
+    skip_whitespace(&mut tokens);
+    while tokens.front().is_some() {
+      if tokens.front() == Some(&Token::LineEnd) {
+        tokens.pop_front();
+      } else {
+        expr_list.push_back(parse_one(&self.arities, &mut tokens, &None)?);
+      }
+      skip_whitespace(&mut tokens);
     }
-    return Ok(AST::ExprLine(expr_list));
+    Ok(expr_list)
   }
 }
 
@@ -277,8 +718,12 @@ pub fn rek_print(item: &AST, prefix: String) {
     AST::Function(name) => {
       println!("{}", name);
     },
-    AST::Nary(token, expr_list) => {
-      println!("Prefix {:?}", token);
+    AST::Call(name, args) => {
+      println!("Call {}", name);
+      print_list(args, prefix);
+    },
+    AST::Nary(operator, expr_list) => {
+      println!("Prefix {:?}", operator);
       print_list(expr_list, prefix);
       // rek_print(&AST::Parens(expr_list.clone()), prefix.clone() + "  ");
     },
@@ -292,8 +737,7 @@ pub fn rek_print(item: &AST, prefix: String) {
       rek_print(right_operand, prefix.clone() + "  ");
     },
     AST::List(list) => {
-      println!("{:?}", "LIST");
-      print_list(list, prefix);
+      println!("{:?}", list);
     },
     AST::Parens(expr_list) => {
       println!("PARENS");
@@ -315,6 +759,52 @@ pub fn rek_print(item: &AST, prefix: String) {
   }
 }
 
+// Output for sample input "1 + (2 * (3 + 4 * -5) + -6 * -(-7 + -8)) * 9":
+// ExprLine([Binary(Plus, Num(1.0), Binary(Multiply, Parens([Binary(Plus, Binary(Multiply, Num(2.0), Parens([Binary(Plus, Num(3.0), Binary(Multiply, Num(4.0), Negation(Num(5.0))))])), Binary(Multiply, Negation(Num(6.0)), Negation(Parens([Binary(Plus, Negation(Num(7.0)), Negation(Num(8.0)))]))))]), Num(9.0)))])
+// Expression line
+// +- Plus
+//   +- 1.0
+//   +- Multiply
+//     +- PARENS
+//     | +- Plus
+//     |   +- Multiply
+//     |   | +- 2.0
+//     |   | +- PARENS
+//     |   |   +- Plus
+//     |   |     +- 3.0
+//     |   |     +- Multiply
+//     |   |       +- 4.0
+//     |   |       +- Negation
+//     |   |         +- 5.0
+//     |   +- Multiply
+//     |     +- Negation
+//     |     | +- 6.0
+//     |     +- Negation
+//     |       +- PARENS
+//     |         +- Plus
+//     |           +- Negation
+//     |           | +- 7.0
+//     |           +- Negation
+//     |             +- 8.0
+//     +- 9.0
+#[allow(dead_code)]
+fn main() {
+  let mut parser = Parser::new();
+  loop {
+    let mut input = String::new();
+    std::io::stdin().read_line(&mut input).unwrap();
+    match parser.parse(&input) {
+      Ok(val) => {
+        println!("{:?}", val);
+        rek_print(&val, "".to_string());
+      },
+      Err(err) => {
+        println!("Parsing error: {:?}", err);
+      },
+    }
+  }
+}
+
 #[cfg(test)]
 mod tests {
   #![allow(non_snake_case, dead_code)]
@@ -338,21 +828,21 @@ mod tests {
     AST::Num(int as f32)
   }
 
-  fn Nary(token: Token, expr_list: &[AST]) -> AST {
-    AST::Nary(token, ListType::from(expr_list.to_vec()))
+  fn Nary(operator: Operator, expr_list: &[AST]) -> AST {
+    AST::Nary(operator, ListType::from(expr_list.to_vec()))
   }
   fn PPlus(expr_list: &[AST]) -> AST {
-    Nary(Token::Plus, expr_list)
+    Nary(Operator::Plus, expr_list)
   }
 
-  fn Binary(token: Token, left: AST, right: AST) -> AST {
-    AST::Binary(token, Box::new(left), Box::new(right))
+  fn Binary(operator: Operator, left: AST, right: AST) -> AST {
+    AST::Binary(operator, Box::new(left), Box::new(right))
   }
 
   macro_rules! gen_binary {
     ($name:ident) => {
       fn $name(left: AST, right: AST) -> AST {
-        Binary(Token::$name, left, right)
+        Binary(Operator::$name, left, right)
       }
     }
   }
@@ -413,57 +903,130 @@ mod tests {
     assert_eq!(AST::None, parser.parse("1 2\\\n").unwrap());
     assert_eq!(AST::ExprLine(ListType::from(vec![AST::Num(1.0), AST::Num(2.0), AST::Num(3.0)])),
                parser.parse("3").unwrap());
+    assert_eq!(AST::None, parser.parse("1 2\\\n").unwrap());
+    assert!(parser.parse("]").is_err());
+    assert_eq!(AST::ExprLine(ListType::from(vec![AST::Num(3.0)])), parser.parse("3").unwrap());
+    let lines: Vec<String> = vec!["  FD 1 \\".to_string(), "FD 2".to_string(), "RT 90".to_string()];
+    assert_eq!(Some("FD 1 FD 2".to_string()), source_line(&lines, 0));
+    assert_eq!(Some("RT 90".to_string()), source_line(&lines, 1));
+    assert_eq!(None, source_line(&lines, 2));
   }
 
-  #[test]
-  fn empty_line() {
+  fn Call(name: &str, args: &[AST]) -> AST {
+    AST::Call(name.to_string(), ListType::from(args.to_vec()))
+  }
+
+  fn W(word: &str) -> Value {
+    Value::Word(word.to_string())
+  }
+
+  fn N(num: NumType) -> Value {
+    Value::Num(num)
+  }
+
+  fn L(items: &[Value]) -> List {
+    List::from(items.to_vec())
+  }
+
+  fn test_parser() -> Parser {
     let mut parser = Parser::new();
-    assert_eq!(AST::ExprLine(ListType::new()), parser.parse("\n").unwrap());
+    parser.set_arity("FD", Arity::fixed(1));
+    parser.set_arity("XCOR", Arity::fixed(0));
+    parser.set_arity("LIST", Arity::new(2, 0, None));
+    parser.set_arity("IF", Arity::new(2, 2, Some(3)));
+    parser
   }
-}
 
-// Output for sample input "1 + (2 * (3 + 4 * -5) + -6 * -(-7 + -8)) * 9":
-// ExprLine([Binary(Plus, Num(1.0), Binary(Multiply, Parens([Binary(Plus, Binary(Multiply, Num(2.0), Parens([Binary(Plus, Num(3.0), Binary(Multiply, Num(4.0), Negation(Num(5.0))))])), Binary(Multiply, Negation(Num(6.0)), Negation(Parens([Binary(Plus, Negation(Num(7.0)), Negation(Num(8.0)))]))))]), Num(9.0)))])
-// Expression line
-// +- Plus
-//   +- 1.0
-//   +- Multiply
-//     +- PARENS
-//     | +- Plus
-//     |   +- Multiply
-//     |   | +- 2.0
-//     |   | +- PARENS
-//     |   |   +- Plus
-//     |   |     +- 3.0
-//     |   |     +- Multiply
-//     |   |       +- 4.0
-//     |   |       +- Negation
-//     |   |         +- 5.0
-//     |   +- Multiply
-//     |     +- Negation
-//     |     | +- 6.0
-//     |     +- Negation
-//     |       +- PARENS
-//     |         +- Plus
-//     |           +- Negation
-//     |           | +- 7.0
-//     |           +- Negation
-//     |             +- 8.0
-//     +- 9.0
-#[allow(dead_code)]
-fn main() {
-  let mut parser = Parser::new();
-  loop {
-    let mut input = String::new();
-    std::io::stdin().read_line(&mut input).unwrap();
-    match parser.parse(&input) {
-      Ok(val) => {
-        println!("{:?}", val);
-        rek_print(&val, "".to_string());
-      },
-      Err(err) => {
-        println!("Parsing error: {:?}", err);
-      },
+  #[test]
+  fn calls() {
+    for (input, expected) in &[
+      ("FD 10 FD XCOR + 5", vec![Call("FD", &[I(10)]), Call("FD", &[Plus(Call("XCOR", &[]), I(5))])]),
+      ("(LIST 1 2 3)", vec![Call("LIST", &[I(1), I(2), I(3)])]),
+      ("(XCOR + 5) * 2", vec![Multiply(Plus(Call("XCOR", &[]), I(5)), I(2))]),
+      ("LIST 1 -2", vec![Call("LIST", &[I(1), Negation(I(2))])]),
+      ("IF 1 THEN FD 1 FD (2) ELSE [FD 3]", vec![Call("IF", &[
+          I(1),
+          AST::List(L(&[W("FD"), N(1.0), W("FD"), W("("), N(2.0), W(")")])),
+          AST::List(L(&[W("FD"), N(3.0)]))])]),
+      ("IF 1 [FD 1] [FD 2]", vec![Call("IF", &[
+          I(1), AST::List(L(&[W("FD"), N(1.0)])), AST::List(L(&[W("FD"), N(2.0)]))])]),
+      ("IF 1 THEN IF 2 THEN FD 1 ELSE FD 2", vec![Call("IF", &[
+          I(1), AST::List(L(&[W("IF"), N(2.0), W("THEN"), W("FD"), N(1.0), W("ELSE"), W("FD"), N(2.0)]))])]),
+      ("TO SQUARE :A", vec![Call("TO", &[AST::Function("SQUARE".to_string()), AST::Var("A".to_string())])]),
+    ] {
+      assert_eq!(Ok(AST::ExprLine(ListType::from(expected.clone()))), test_parser().parse(input),
+                 "\ninput: {}", input);
+    }
+    for (input, expected) in &[
+      ("FD", "Not enough inputs to FD"),
+      ("FD LIST 1", "Not enough inputs to LIST"),
+      ("(FD 1 2)", "Too many inputs to FD"),
+      ("(1 2)", "Too much inside ()"),
+      ("SQUARE 10", "I don't know how to SQUARE"),
+      ("FD 1 +", "Not enough inputs to +"),
+      ("FD + 1", "Not enough inputs to +"),
+      ("FD -:A", "- doesn't like :A as input"),
+      ("FD (1 + 2", "')' not found"),
+      ("FD 1)", "Unexpected ')'"),
+      ("FD 1 @", "I don't know how to @"),
+      ("TO SQUARE :A [", "TO doesn't like [ as input"),
+    ] {
+      assert_eq!(Err(expected.to_string()), test_parser().parse(input).map_err(|err| err.to_string()),
+                 "\ninput: {}", input);
     }
   }
+
+  #[test]
+  fn lists_are_data() {
+    test_line_ok("[FD :A*2 \"X [-1 (2)]]", &[AST::List(L(&[
+      W("FD"), W(":A*2"), W("\"X"),
+      Value::List(L(&[N(-1.0), W("("), N(2.0), W(")")])),
+    ]))]);
+    let list = L(&[W("FD"), W(":A*2"), Value::List(L(&[N(1.0)]))]);
+    assert_eq!(Err(Box::new(LogoError::from(ErrorKind::DontKnowHow("FD".to_string())))),
+               Parser::new().parse_list(&list));
+    let mut parser = test_parser();
+    parser.set_arity("REPEAT", Arity::fixed(2));
+    assert_eq!(Ok(Rc::new(ListType::from(vec![
+                 Call("FD", &[Multiply(AST::Var("A".to_string()), I(2))]),
+                 AST::List(L(&[N(1.0)]))]))),
+               parser.parse_list(&list));
+    // Parsed once, and again only after the arities change.
+    let code = parser.parse_list(&list).unwrap();
+    assert!(Rc::ptr_eq(&code, &parser.parse_list(&list.clone()).unwrap()));
+    parser.set_arity("SQUARE", Arity::fixed(1));
+    assert!(!Rc::ptr_eq(&code, &parser.parse_list(&list).unwrap()));
+  }
+
+  #[test]
+  fn arrays() {
+    test_line_ok("{1 [2 X]}@0 {}@-1", &[
+      AST::Array(L(&[N(1.0), Value::List(L(&[N(2.0), W("X")]))]), 0), AST::Array(L(&[]), -1),
+    ]);
+    // Arrays inside lists are data, they're made when the list is read.
+    let list = match Parser::new().parse("[1 {2 {3}@0}]").unwrap() {
+      AST::ExprLine(exprs) => { exprs[0].clone() },
+      other => { other },
+    };
+    match list {
+      AST::List(list) => { assert_eq!("[1 {2 {3}@0}]", Value::List(list).to_string()); },
+      other => { panic!("{:?}", other); },
+    }
+    for (input, expected) in &[
+      ("{1 2", "'}' not found"),
+      ("[1 2}", "Unexpected '}'"),
+      ("{1]", "Unexpected ']'"),
+      ("{1}@X", "@ doesn't like X as input"),
+      ("{1}@", "Not enough inputs to @"),
+    ] {
+      assert_eq!(Err(expected.to_string()), Parser::new().parse(input).map_err(|err| err.to_string()),
+                 "\ninput: {}", input);
+    }
+  }
+
+  #[test]
+  fn empty_line() {
+    let mut parser = Parser::new();
+    assert_eq!(AST::ExprLine(ListType::new()), parser.parse("\n").unwrap());
+  }
 }
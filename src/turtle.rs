@@ -18,7 +18,7 @@ pub struct Turtle {
 impl Turtle {
   pub fn new(graphics: Box<dyn Graphics>) -> Turtle {
     Turtle {
-      graphics: graphics,
+      graphics,
       heading: 0.0,
       x: 0.0,
       y: 0.0,
@@ -134,7 +134,7 @@ impl PartialEq for Command {
 // Returns a Vec of Command::Line connecting all the points:
 // [(p0, p1), (p1, p2), ..., (pN-1, pN)]
 #[allow(dead_code)]
-pub fn points_to_line_commands(points: &Vec<(f32, f32)>) -> Vec<Command> {
+pub fn points_to_line_commands(points: &[(f32, f32)]) -> Vec<Command> {
   let mut ret = vec![];
   for i in 0..points.len().max(1)-1 {
     ret.push(Command::Line(points[i], points[i + 1]));
@@ -146,13 +146,8 @@ pub fn points_to_line_commands(points: &Vec<(f32, f32)>) -> Vec<Command> {
 macro_rules! CON {
   ( $( $x:expr ),* $(,)? ) => {
       {
-          #[allow(unused_mut)] // The linter gets this totally wrong.
-          let mut temp_vec = Vec::new();
-          $(
-              temp_vec.push($x);
-          )*
           use turtle;
-          turtle::points_to_line_commands(&temp_vec)
+          turtle::points_to_line_commands(&[$($x),*])
       }
   };
 }
@@ -180,6 +175,28 @@ impl GraphicsStub {
   }
 }
 
+// Sends the commands to the thread drawing them, the evaluator runs on a thread of its own.
+pub struct GraphicsSender {
+  sender: std::sync::mpsc::Sender<Command>,
+}
+
+impl Graphics for GraphicsSender {
+  fn line(&mut self, p1: (f32, f32), p2: (f32, f32)) {
+    // Nobody draws them any more once the window is closed.
+    let _ = self.sender.send(Command::Line(p1, p2));
+  }
+
+  fn clear(&mut self) {
+    let _ = self.sender.send(Command::Clear);
+  }
+}
+
+impl GraphicsSender {
+  pub fn new(sender: std::sync::mpsc::Sender<Command>) -> GraphicsSender {
+    GraphicsSender { sender }
+  }
+}
+
 #[cfg(test)]
 mod tests {
   #![allow(non_snake_case)]
@@ -0,0 +1,268 @@
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+use compiler::Code;
+use parser::{ListType, NumType, WordType};
+
+// Runtime values, what the evaluator works with.  The parser AST only gets involved when a list
+// is run as code.  Lists and arrays are shared, cloning one doesn't copy its items.  Procedures
+// are referred to by their names, the words APPLY, MAP, ... take (APPLY "FPUT [1 [2]]).
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+  Num(NumType),
+  Word(WordType),
+  List(List),
+  Array(Array),
+}
+
+// How PRINT shows a value inside a list, and the error messages show it: [1 [2 3]], {1 2}.
+impl fmt::Display for Value {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    match self {
+      Value::Num(num) => { write!(f, "{}", num) },
+      Value::Word(word) => { write!(f, "{}", word) },
+      Value::List(list) => { write!(f, "[{}]", join(list.iter())) },
+      Value::Array(array) => {
+        write!(f, "{{{}}}", join(array.items.borrow().iter()))?;
+        if array.origin != 1 {
+          write!(f, "@{}", array.origin)?;
+        }
+        Ok(())
+      },
+    }
+  }
+}
+
+fn join<'a, I: Iterator<Item = &'a Value>>(items: I) -> String {
+  items.map(|item| item.to_string()).collect::<Vec<String>>().join(" ")
+}
+
+// View into shared, immutable items.  BUTFIRST and BUTLAST only move the ends of the view, and
+// cloning a list is just cloning the Rc.
+#[derive(Clone, Default)]
+pub struct List {
+  items: Rc<Vec<Value>>,
+  start: usize,
+  end: usize,
+  // The list parsed as code the last time it was run, shared by the clones, so REPEAT in a
+  // procedure body gets parsed only once.
+  code: Rc<RefCell<Option<ListCode>>>,
+}
+
+struct ListCode {
+  // Version of the parser it was parsed with.
+  version: usize,
+  exprs: Rc<ListType>,
+  compiled: Option<Rc<Code>>,
+}
+
+impl List {
+  pub fn new() -> Self {
+    List::default()
+  }
+
+  pub fn len(&self) -> usize {
+    self.end - self.start
+  }
+
+  pub fn is_empty(&self) -> bool {
+    self.start == self.end
+  }
+
+  pub fn as_slice(&self) -> &[Value] {
+    &self.items[self.start .. self.end]
+  }
+
+  pub fn iter(&self) -> std::slice::Iter<'_, Value> {
+    self.as_slice().iter()
+  }
+
+  pub fn get(&self, index: usize) -> Option<&Value> {
+    self.as_slice().get(index)
+  }
+
+  pub fn first(&self) -> Option<&Value> {
+    self.as_slice().first()
+  }
+
+  pub fn last(&self) -> Option<&Value> {
+    self.as_slice().last()
+  }
+
+  pub fn butfirst(&self) -> Option<List> {
+    if self.is_empty() {
+      return None;
+    }
+    Some(List { start: self.start + 1, code: Default::default(), ..self.clone() })
+  }
+
+  pub fn butlast(&self) -> Option<List> {
+    if self.is_empty() {
+      return None;
+    }
+    Some(List { end: self.end - 1, code: Default::default(), ..self.clone() })
+  }
+
+  pub fn code(&self, version: usize) -> Option<Rc<ListType>> {
+    match *self.code.borrow() {
+      Some(ref code) if code.version == version => { Some(code.exprs.clone()) },
+      _ => { None },
+    }
+  }
+
+  pub fn set_code(&self, version: usize, exprs: Rc<ListType>) {
+    *self.code.borrow_mut() = Some(ListCode { version, exprs, compiled: None });
+  }
+
+  // The compiled code, if it was compiled from these parsed exprs.
+  pub fn compiled(&self, exprs: &Rc<ListType>) -> Option<Rc<Code>> {
+    match *self.code.borrow() {
+      Some(ref code) if Rc::ptr_eq(&code.exprs, exprs) => { code.compiled.clone() },
+      _ => { None },
+    }
+  }
+
+  pub fn set_compiled(&self, compiled: Rc<Code>) {
+    if let Some(ref mut code) = *self.code.borrow_mut() {
+      code.compiled = Some(compiled);
+    }
+  }
+
+  // FPUT and LPUT make a new list, the old one can still be shared by someone else.
+  pub fn fput(&self, value: Value) -> List {
+    std::iter::once(value).chain(self.iter().cloned()).collect()
+  }
+
+  pub fn lput(&self, value: Value) -> List {
+    self.iter().cloned().chain(std::iter::once(value)).collect()
+  }
+}
+
+impl From<Vec<Value>> for List {
+  fn from(items: Vec<Value>) -> Self {
+    let end = items.len();
+    List { items: Rc::new(items), start: 0, end, code: Default::default() }
+  }
+}
+
+impl std::iter::FromIterator<Value> for List {
+  fn from_iter<I: IntoIterator<Item = Value>>(iter: I) -> Self {
+    List::from(iter.into_iter().collect::<Vec<Value>>())
+  }
+}
+
+impl<'a> IntoIterator for &'a List {
+  type Item = &'a Value;
+  type IntoIter = std::slice::Iter<'a, Value>;
+
+  fn into_iter(self) -> Self::IntoIter {
+    self.iter()
+  }
+}
+
+impl PartialEq for List {
+  fn eq(&self, other: &List) -> bool {
+    self.as_slice() == other.as_slice()
+  }
+}
+
+impl fmt::Debug for List {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    f.debug_list().entries(self.iter()).finish()
+  }
+}
+
+// Arrays can be changed in place (SETITEM), everyone sharing the array sees the change.
+#[derive(Clone)]
+pub struct Array {
+  items: Rc<RefCell<Vec<Value>>>,
+  // Index of the first item, 1 unless given otherwise.
+  pub origin: i32,
+}
+
+impl Array {
+  pub fn new(items: Vec<Value>, origin: i32) -> Self {
+    Array { items: Rc::new(RefCell::new(items)), origin }
+  }
+
+  pub fn len(&self) -> usize {
+    self.items.borrow().len()
+  }
+
+  // Position in the items of the index counted from the origin.
+  fn position(&self, index: i32) -> Option<usize> {
+    let position = index.checked_sub(self.origin)?;
+    if position >= 0 && (position as usize) < self.len() { Some(position as usize) } else { None }
+  }
+
+  pub fn get(&self, index: i32) -> Option<Value> {
+    self.position(index).map(|position| self.items.borrow()[position].clone())
+  }
+
+  // False when the index is out of range.
+  pub fn set(&self, index: i32, value: Value) -> bool {
+    match self.position(index) {
+      Some(position) => {
+        self.items.borrow_mut()[position] = value;
+        true
+      },
+      None => { false },
+    }
+  }
+
+  pub fn to_list(&self) -> List {
+    List::from(self.items.borrow().clone())
+  }
+}
+
+// Two arrays are equal only if they're the same array.
+impl PartialEq for Array {
+  fn eq(&self, other: &Array) -> bool {
+    Rc::ptr_eq(&self.items, &other.items)
+  }
+}
+
+impl fmt::Debug for Array {
+  fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+    write!(f, "{{")?;
+    f.debug_list().entries(self.items.borrow().iter()).finish()?;
+    write!(f, "}}")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn nums(nums: &[NumType]) -> List {
+    nums.iter().map(|&num| Value::Num(num)).collect()
+  }
+
+  #[test]
+  fn shared_list() {
+    let list = nums(&[1.0, 2.0, 3.0]);
+    let rest = list.butfirst().unwrap().butlast().unwrap();
+    assert_eq!(nums(&[2.0]), rest);
+    assert!(Rc::ptr_eq(&list.items, &rest.items));
+    assert_eq!(nums(&[0.0, 2.0]), rest.fput(Value::Num(0.0)));
+    assert_eq!(nums(&[2.0, 4.0]), rest.lput(Value::Num(4.0)));
+    assert_eq!(nums(&[1.0, 2.0, 3.0]), list);
+    assert_eq!(None, List::new().butfirst());
+    assert_eq!("[Num(2.0)]", format!("{:?}", rest));
+    let nested = Value::List(List::from(vec![Value::Num(1.5), Value::List(rest)]));
+    assert_eq!("[1.5 [2]]", nested.to_string());
+  }
+
+  #[test]
+  fn shared_array() {
+    let array = Array::new(vec![Value::Num(1.0), Value::Num(2.0)], 0);
+    let shared = array.clone();
+    assert!(shared.set(1, Value::Num(5.0)));
+    assert!(!shared.set(2, Value::Num(5.0)));
+    assert_eq!((Some(Value::Num(5.0)), None, None), (array.get(1), array.get(2), array.get(-1)));
+    assert_eq!(nums(&[1.0, 5.0]), array.to_list());
+    assert_eq!(array, shared);
+    assert!(array != Array::new(vec![Value::Num(1.0), Value::Num(5.0)], 0));
+    assert_eq!("{1 5}@0", Value::Array(array).to_string());
+  }
+}